@@ -1,4 +1,6 @@
-mod lox;
+#![allow(clippy::needless_return)]
+
+pub mod lox;
 
 use wasm_bindgen::prelude::*;
 
@@ -13,11 +15,36 @@ extern "C" {
 
 #[wasm_bindgen]
 pub fn run(source: &str) -> JsValue {
-    let mut result = String::new();
+    let (output, diagnostics) = interpret(source);
+    let mut result = output.text + &output.error_text;
+    // The playground shows one block of text, so the program's error output
+    // follows its output, then the diagnostics, uncoloured.
+    for diagnostic in diagnostics.iter() {
+        result.push_str(&format!("{}\n", diagnostics.render(diagnostic, false)));
+    }
+    return JsValue::from_str(&result);
+}
+
+/// Like `run`, but returns a JSON object with the program's `output` and
+/// `error_output` kept apart, and its `diagnostics`, each shaped as
+/// `rlox --error-format=json` prints them.
+#[wasm_bindgen]
+pub fn run_json(source: &str) -> JsValue {
+    let (output, diagnostics) = interpret(source);
+    return JsValue::from_str(&diagnostics.to_json_report(&output));
+}
+
+fn interpret(source: &str) -> (lox::Output, lox::diagnostics::Diagnostics) {
+    let mut result = lox::Output::default();
+    let mut diagnostics = lox::diagnostics::Diagnostics::new();
+    let options = lox::Options::default();
+    let environment = lox::globals(&options, &mut result, &mut diagnostics);
     lox::run(
         String::from(source),
-        &mut Box::new(lox::environment::Environment::new()),
+        &environment,
+        &options,
         &mut result,
+        &mut diagnostics,
     );
-    return JsValue::from_str(&format!("{}", result));
+    return (result, diagnostics);
 }
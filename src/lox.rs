@@ -1,73 +1,387 @@
+mod checker;
+pub mod diagnostics;
 pub mod environment;
 pub mod expr;
+mod format;
+mod generator;
 mod interpreter;
+mod json;
+mod lint;
+mod natives;
 mod parser;
+mod random;
+pub mod sandbox;
 mod scanner;
 mod stmt;
 pub mod tokens;
 
-static mut HAD_ERROR: bool = false;
-
+use std::cell::RefCell;
 use std::rc::Rc;
 
-pub fn run_prompt() {
+/// Settings for embedding the interpreter, chosen once when the global
+/// scope is created.
+#[derive(Clone)]
+pub struct Options {
+    /// Which directories the file natives may read and write.
+    pub sandbox: sandbox::Sandbox,
+    /// The seed for the random natives, or `None` to seed from the clock.
+    pub seed: Option<u64>,
+    /// Leaves out the prelude, as `--no-prelude` does.
+    pub no_prelude: bool,
+    /// How many lines of backtrace a runtime error shows, as
+    /// `--backtrace-limit` sets.
+    pub backtrace_limit: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        return Options {
+            sandbox: sandbox::Sandbox::default(),
+            seed: None,
+            no_prelude: false,
+            backtrace_limit: 16,
+        };
+    }
+}
+
+/// Everything a program prints: `print` writes to `text` and `eprint` to
+/// `error_text`, which the CLI shows on standard output and standard error.
+/// It also counts the calls running, as the one thing a run carries through
+/// every call.
+#[derive(Default)]
+pub struct Output {
+    pub text: String,
+    pub error_text: String,
+    depth: usize,
+}
+
+/// How many calls, including generators being resumed, may run inside one
+/// another before it is a runtime error rather than a host stack overflow.
+/// The command line runs programs on a thread with a stack big enough for
+/// this many; an embedder running them on a small stack may need more.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+impl Output {
+    /// Counts a call starting, failing with "Stack overflow." instead when
+    /// `MAX_CALL_DEPTH` calls are already running.
+    pub(crate) fn enter_call(&mut self) -> Result<(), interpreter::RuntimeError> {
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(interpreter::RuntimeError::new(String::from(
+                "Stack overflow.",
+            )));
+        }
+        self.depth += 1;
+        return Ok(());
+    }
+
+    pub(crate) fn leave_call(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+/// Library functions written in Lox, run in every global scope before the
+/// program unless `Options::no_prelude` is set.
+const PRELUDE: &str = include_str!("lox/prelude.lox");
+
+/// Reads and runs lines from standard input until it closes. Each line's
+/// output is shown once it has run, followed by its error output and
+/// diagnostics on standard error, and an error in one line leaves the session running.
+/// With `json`, each diagnostic is written as a line of JSON, as
+/// `--error-format=json` asks.
+pub fn run_prompt(options: &Options, json: bool) {
     let stdin = std::io::stdin();
     let mut buffer = String::default();
-    let mut environment = Box::new(environment::Environment::new());
-    let mut output = String::new();
-    while stdin.read_line(&mut buffer).unwrap() != 0 {
-        run(buffer, &mut environment, &mut output);
-        println!("{:?}", output);
-        output = String::new();
+    let mut output = Output::default();
+    let mut diagnostics = diagnostics::Diagnostics::new();
+    let environment = globals(options, &mut output, &mut diagnostics);
+    let colour = diagnostics::colour_stderr();
+    loop {
+        if !output.text.is_empty() {
+            println!("{:?}", output.text);
+        }
+        eprint!("{}", output.error_text);
+        output = Output::default();
+        for diagnostic in diagnostics.iter() {
+            if json {
+                eprintln!("{}", diagnostics.to_json(diagnostic));
+            } else {
+                eprintln!("{}", diagnostics.render(diagnostic, colour));
+            }
+        }
+        diagnostics.take();
+        if stdin.read_line(&mut buffer).unwrap() == 0 {
+            break;
+        }
+        run(buffer, &environment, options, &mut output, &mut diagnostics);
         buffer = String::default();
     }
 }
 
-pub fn run_file(path: &String, output: &mut String) {
-    let mut environment = environment::Environment::new();
-    run(
-        std::fs::read_to_string(path).unwrap(),
-        &mut Box::new(environment),
-        output,
-    );
+pub fn run_file(
+    path: &str,
+    options: &Options,
+    output: &mut Output,
+    diagnostics: &mut diagnostics::Diagnostics,
+) {
+    let environment = globals(options, output, diagnostics);
+    if let Some(source) = read_source(path, diagnostics) {
+        let mut found = diagnostics.nested();
+        run(source, &environment, options, output, &mut found);
+        diagnostics.extend_from_file(path, found);
+    }
 }
 
-pub fn run(source: String, environment: &mut Box<environment::Environment>, output: &mut String) {
-    let scn = scanner::Scanner::new(source);
-    let tokens = scn.scan_tokens();
+fn read_source(path: &str, diagnostics: &mut diagnostics::Diagnostics) -> Option<String> {
+    match std::fs::read_to_string(path) {
+        Ok(source) => Some(source),
+        Err(error) => {
+            let message = format!("Could not read {}: {}.", path, error);
+            diagnostics.report(diagnostics::Diagnostic::error(None, message).with_code("io-error"));
+            None
+        }
+    }
+}
 
-    /* for token in tokens.iter() {
-        println!("{}", token);
-    } */
+/// A fresh global scope for a program, inside one holding the built in
+/// natives such as `math` and, unless `options` leaves it out, the functions
+/// the prelude defines. The program's own globals are nearer, so they are
+/// suggested ahead of built in names for a misspelling. Diagnostics from the
+/// prelude are marked as coming from it rather than the program.
+pub fn globals(
+    options: &Options,
+    output: &mut Output,
+    diagnostics: &mut diagnostics::Diagnostics,
+) -> Rc<RefCell<environment::Environment>> {
+    let mut environment = environment::Environment::new();
+    natives::define(&mut environment, options);
+    let environment = Rc::new(RefCell::new(environment));
+    if !options.no_prelude {
+        let mut prelude = diagnostics.nested();
+        run(
+            String::from(PRELUDE),
+            &environment,
+            options,
+            output,
+            &mut prelude,
+        );
+        diagnostics.extend_from_file("prelude", prelude);
+    }
+    return Rc::new(RefCell::new(environment::Environment::with_parent(
+        environment,
+    )));
+}
 
-    let mut parser = parser::Parser::new(tokens);
-    let program = parser.parse();
+/// Runs `source` in `environment`. Nothing runs if it has a syntax error,
+/// and it stops at the first runtime error, which is reported with a
+/// backtrace of the calls it was raised in.
+pub fn run(
+    source: String,
+    environment: &Rc<RefCell<environment::Environment>>,
+    options: &Options,
+    output: &mut Output,
+    diagnostics: &mut diagnostics::Diagnostics,
+) {
+    let program = match parse(source, diagnostics) {
+        Some(program) => program,
+        None => return,
+    };
 
     for stmt in program.iter() {
-        println!("Stmt: {}", stmt);
-        stmt.interpret(environment, output);
+        let result = match stmt.interpret(environment, output) {
+            Ok(stmt::Flow::Normal) => Ok(()),
+            Ok(stmt::Flow::TailCall(call)) => expr::Expr::call(call, output).map(|_| ()),
+            // The parser refuses a `return` outside a function, so this
+            // only happens if a program is built some other way.
+            Ok(stmt::Flow::Return(_)) => Err(interpreter::RuntimeError {
+                span: Some(stmt.span()),
+                ..interpreter::RuntimeError::new(String::from("Cannot return from top-level code."))
+            }),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            let backtrace = error.backtrace(options.backtrace_limit);
+            diagnostics.report(diagnostics::Diagnostic {
+                notes: backtrace,
+                ..diagnostics::Diagnostic::from(error)
+            });
+            return;
+        }
+    }
+}
+
+/// Scans and parses `source`, returning the program only if it has no
+/// errors.
+fn parse(source: String, diagnostics: &mut diagnostics::Diagnostics) -> Option<Vec<stmt::Stmt>> {
+    let id = diagnostics.add_source(None, source.clone());
+    let mut found = diagnostics::Diagnostics::new();
+    let (tokens, errors) = scanner::Scanner::new(source, id).scan_tokens();
+    found.extend(errors);
+    let program = match parser::Parser::new(tokens).parse() {
+        Ok(program) => Some(program),
+        Err(errors) => {
+            found.extend(errors.into_iter().map(diagnostics::Diagnostic::from));
+            None
+        }
+    };
+    let failed = found.has_errors();
+    diagnostics.extend(found.take());
+    if failed {
+        return None;
     }
+    return program;
 }
 
+pub fn check_file(path: &str, diagnostics: &mut diagnostics::Diagnostics) -> bool {
+    match read_source(path, diagnostics) {
+        Some(source) => {
+            let mut found = diagnostics.nested();
+            let well_typed = check(source, &mut found);
+            diagnostics.extend_from_file(path, found);
+            well_typed
+        }
+        None => false,
+    }
+}
+
+/// Type checks `source` without running it, reporting every mismatch between
+/// annotated types, and lints it, warning about likely mistakes. Returns
+/// whether the program is well typed.
+pub fn check(source: String, diagnostics: &mut diagnostics::Diagnostics) -> bool {
+    let program = match parse(source.clone(), diagnostics) {
+        Some(program) => program,
+        None => return false,
+    };
+
+    let errors = checker::Checker::new().check(&program);
+    let well_typed = errors.is_empty();
+    diagnostics.extend(errors.into_iter().map(diagnostics::Diagnostic::from));
+    diagnostics.extend(lint::Linter::new(&source).lint(&program));
+    return well_typed;
+}
+
+/// Prints the tokens of `source` and then any diagnostics from scanning and
+/// parsing it.
 pub fn evaluate_run(source: String) {
-    let scn = scanner::Scanner::new(source);
-    let tokens = scn.scan_tokens();
+    let mut diagnostics = diagnostics::Diagnostics::new();
+    let id = diagnostics.add_source(None, source.clone());
+    let (tokens, errors) = scanner::Scanner::new(source, id).scan_tokens();
+    diagnostics.extend(errors);
 
     for token in tokens.iter() {
         println!("{}", token);
     }
 
-    let mut parser = parser::Parser::new(tokens);
-    let program = parser.parse();
+    if let Err(errors) = parser::Parser::new(tokens).parse() {
+        diagnostics.extend(errors.into_iter().map(diagnostics::Diagnostic::from));
+    }
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
 }
 
-pub fn error(line: usize, message: &str) {
-    report(line, "", message);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `source` prints and the message of each diagnostic, run on a
+    /// thread with a stack as big as the command line's.
+    fn run_program(source: &'static str) -> (String, Vec<String>) {
+        let program = std::thread::Builder::new()
+            .stack_size(512 * 1024 * 1024)
+            .spawn(move || {
+                let options = Options {
+                    no_prelude: true,
+                    ..Options::default()
+                };
+                let mut output = Output::default();
+                let mut diagnostics = diagnostics::Diagnostics::new();
+                let environment = globals(&options, &mut output, &mut diagnostics);
+                let source = String::from(source);
+                run(
+                    source,
+                    &environment,
+                    &options,
+                    &mut output,
+                    &mut diagnostics,
+                );
+                let messages = diagnostics.iter().map(|d| d.message.clone()).collect();
+                (output.text, messages)
+            })
+            .unwrap();
+        return program.join().unwrap();
+    }
+
+    #[test]
+    fn limits_call_depth() {
+        let source = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); }\n\
+                      print f(900);\n\
+                      print f(10000);";
+        let (text, messages) = run_program(source);
+        assert_eq!(text, "900\n");
+        assert_eq!(messages, vec!["Stack overflow."]);
+    }
+
+    #[test]
+    fn limits_generator_depth() {
+        let source = "fun count(n) { if (n > 0) { for (x in count(n - 1)) yield x; } yield n; }\n\
+                      var total = 0;\n\
+                      for (x in count(10000)) total = total + x;";
+        assert_eq!(run_program(source).1, vec!["Stack overflow."]);
+    }
+
+    #[test]
+    fn prints_lists_and_maps_that_contain_themselves() {
+        let source = "var l = [1]; l.push(l); print l;\n\
+                      var m = json.parse(\"{}\"); m[\"self\"] = m; print m;\n\
+                      print [l, m, l];";
+        let (text, messages) = run_program(source);
+        assert_eq!(
+            text,
+            "[1, [...]]\n{self: {...}}\n[[1, [...]], {self: {...}}, [1, [...]]]\n"
+        );
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn compares_lists_that_contain_themselves() {
+        let source = "var l = []; l.push(l); print l == l; print l != [];\n\
+                      var a = []; a.push(a); var b = []; b.push(b); print a == b;";
+        let (text, messages) = run_program(source);
+        assert_eq!(text, "true\ntrue\n");
+        assert_eq!(
+            messages,
+            vec!["Cannot compare values nested more than 512 deep."]
+        );
+    }
+
+    #[test]
+    fn runs_tail_calls_at_any_depth() {
+        let source = "fun f(n) { if (n == 0) return \"done\"; return f(n - 1); }\n\
+                      print f(100000);";
+        assert_eq!(run_program(source), (String::from("done\n"), vec![]));
+    }
+
+    #[test]
+    fn limits_range_length() {
+        let (text, messages) = run_program("print range(3).len(); print range(1000000000);");
+        assert_eq!(text, "3\n");
+        assert_eq!(
+            messages,
+            vec!["range can make at most 10000000 numbers, but was asked for 1000000000."]
+        );
+    }
+
+    #[test]
+    fn refuses_a_substring_end_that_is_not_whole() {
+        let (text, messages) =
+            run_program("print \"hello\".substring(1, 3); print \"hello\".substring(0, 1.5);");
+        assert_eq!(text, "el\n");
+        assert_eq!(messages, vec!["Substring 0..1.5 out of range."]);
+    }
 
-pub fn report(line: usize, location: &str, message: &str) {
-    println!("[line {}] Error{}: {}", line, location, message);
-    unsafe {
-        HAD_ERROR = true;
+    #[test]
+    fn says_argument_for_a_single_argument() {
+        let (_, messages) = run_program("print \"hello\".ends_with();");
+        assert_eq!(messages, vec!["Expected 1 argument but got 0."]);
     }
 }
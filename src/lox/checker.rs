@@ -0,0 +1,808 @@
+use crate::lox::{
+    diagnostics::Label,
+    expr::Expr,
+    interpreter::argument_count,
+    stmt::{FunctionDecl, Pattern, Stmt},
+    tokens::{Span, Token, TokenType},
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Number,
+    String,
+    Boolean,
+    Nil,
+    List,
+    Map,
+    /// A function, with its signature when it is statically known. The bare
+    /// `fun` annotation accepts any function.
+    Function(Option<Rc<Signature>>),
+    /// A class declaration, which constructs instances when called.
+    Class(String),
+    /// An instance of the named class. Annotating with a class name gives
+    /// this type.
+    Instance(String),
+}
+
+#[derive(PartialEq)]
+pub struct ParamType {
+    pub name: String,
+    pub param_type: Type,
+    pub optional: bool,
+}
+
+#[derive(PartialEq)]
+pub struct Signature {
+    pub params: Vec<ParamType>,
+    pub rest: bool,
+    pub returns: Type,
+}
+
+impl Type {
+    fn from_annotation(annotation: &Token) -> Option<Type> {
+        match &annotation.token_type {
+            TokenType::Nil => Some(Type::Nil),
+            TokenType::Fun => Some(Type::Function(None)),
+            TokenType::Identifier(name) => match &name[..] {
+                "any" => Some(Type::Any),
+                "num" => Some(Type::Number),
+                "str" => Some(Type::String),
+                "bool" => Some(Type::Boolean),
+                "list" => Some(Type::List),
+                "map" => Some(Type::Map),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether a value of type `source` may be stored where `self` is expected.
+    /// `any` is compatible with everything in both directions, which is what
+    /// keeps unannotated code dynamically typed. An instance of a class is
+    /// accepted where any class it inherits from, through `superclasses`, is
+    /// expected.
+    pub fn accepts(&self, source: &Type, superclasses: &HashMap<String, String>) -> bool {
+        match (self, source) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Instance(expected), Type::Instance(class)) => {
+                let mut class = class;
+                // At most one step per class, in case the classes inherit
+                // from each other in a loop.
+                for _ in 0..=superclasses.len() {
+                    if class == expected {
+                        return true;
+                    }
+                    match superclasses.get(class) {
+                        Some(superclass) => class = superclass,
+                        None => return false,
+                    }
+                }
+                return false;
+            }
+            (Type::Function(None), Type::Function(_)) => true,
+            (Type::Function(Some(signature)), Type::Function(Some(other))) => {
+                signature.params.len() == other.params.len()
+                    && signature.rest == other.rest
+                    && signature
+                        .params
+                        .iter()
+                        .zip(other.params.iter())
+                        .all(|(param, other)| {
+                            other.param_type.accepts(&param.param_type, superclasses)
+                        })
+                    && signature.returns.accepts(&other.returns, superclasses)
+            }
+            _ => self == source,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Number => write!(f, "num"),
+            Type::String => write!(f, "str"),
+            Type::Boolean => write!(f, "bool"),
+            Type::Nil => write!(f, "nil"),
+            Type::List => write!(f, "list"),
+            Type::Map => write!(f, "map"),
+            Type::Function(None) => write!(f, "fun"),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+            Type::Function(Some(signature)) => {
+                write!(f, "fun(")?;
+                for (i, param) in signature.params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param.param_type)?;
+                    if param.optional {
+                        write!(f, "?")?;
+                    }
+                }
+                if signature.rest {
+                    if !signature.params.is_empty() {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "...")?;
+                }
+                write!(f, "): {}", signature.returns)
+            }
+        }
+    }
+}
+
+pub struct TypeError {
+    pub span: Span,
+    /// The token the error is at, when it is about a single token.
+    pub near: Option<String>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub help: Option<String>,
+}
+
+impl TypeError {
+    fn new(token: &Token, message: String) -> TypeError {
+        return TypeError {
+            near: Some(token.lexeme.clone()),
+            ..TypeError::spanning(token.span(), message)
+        };
+    }
+
+    fn spanning(span: Span, message: String) -> TypeError {
+        return TypeError {
+            span,
+            near: None,
+            message,
+            labels: vec![],
+            help: None,
+        };
+    }
+}
+
+/// Gradual type checker over the parsed program. Only annotated declarations
+/// are given a static type; everything else is `any` and never reported.
+pub struct Checker {
+    scopes: Vec<HashMap<String, Type>>,
+    /// The class each class declared so far inherits from, by name.
+    superclasses: HashMap<String, String>,
+    return_types: Vec<Type>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        return Checker {
+            scopes: vec![HashMap::new()],
+            superclasses: HashMap::new(),
+            return_types: vec![],
+            errors: vec![],
+        };
+    }
+
+    pub fn check(mut self, program: &[Stmt]) -> Vec<TypeError> {
+        self.check_block(program);
+        return self.errors;
+    }
+
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        // Functions may be called before their declaration is reached, so
+        // their signatures are known for the whole block. Classes come first
+        // so signatures can name them.
+        for stmt in stmts.iter() {
+            if let Stmt::Class(declaration) = stmt {
+                let name = declaration.name.lexeme.clone();
+                if let Some(superclass) = &declaration.superclass {
+                    self.superclasses
+                        .insert(name.clone(), superclass.lexeme.clone());
+                }
+                self.declare(&declaration.name, Type::Class(name));
+            }
+        }
+        for stmt in stmts.iter() {
+            if let Stmt::Function(declaration) = stmt {
+                let signature = self.signature(declaration);
+                self.declare(&declaration.name, signature);
+            }
+        }
+        for stmt in stmts.iter() {
+            self.check_stmt(stmt);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.check_expr(expr);
+            }
+            Stmt::Print(_, values) | Stmt::Eprint(_, values) => {
+                for value in values.iter() {
+                    self.check_expr(value);
+                }
+            }
+            Stmt::Var(_, name, annotation) => {
+                let declared = self.resolve(annotation);
+                self.declare(name, declared);
+            }
+            Stmt::InitialisedVar(_, name, annotation, initialiser) => {
+                let value = self.check_expr(initialiser);
+                let declared = self.resolve(annotation);
+                if !declared.accepts(&value, &self.superclasses) {
+                    self.mismatch(
+                        name,
+                        format!(
+                            "Cannot initialise '{}' of type {} with a value of type {}.",
+                            name.lexeme, declared, value
+                        ),
+                        initialiser,
+                        &value,
+                    );
+                }
+                self.declare(name, declared);
+            }
+            Stmt::Block(_, stmts) => {
+                self.scopes.push(HashMap::new());
+                self.check_block(stmts);
+                self.scopes.pop();
+            }
+            Stmt::If(_, condition, consequent, alternate) => {
+                self.check_expr(condition);
+                self.check_stmt(consequent);
+                if let Some(alternate) = alternate {
+                    self.check_stmt(alternate);
+                }
+            }
+            Stmt::While(_, condition, body) => {
+                self.check_expr(condition);
+                self.check_stmt(body);
+            }
+            Stmt::ForIn(_, variable, iterable, body) => {
+                self.check_expr(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(variable, Type::Any);
+                self.check_stmt(body);
+                self.scopes.pop();
+            }
+            Stmt::Yield(_, value) => {
+                if let Some(value) = value {
+                    self.check_expr(value);
+                }
+            }
+            Stmt::Function(declaration) => {
+                // The signature was resolved when the enclosing block was
+                // entered, so annotation errors are only reported once.
+                if let Type::Function(Some(signature)) = self.lookup(&declaration.name) {
+                    self.check_function(declaration, &signature);
+                }
+            }
+            Stmt::Class(declaration) => {
+                if let Some(superclass) = &declaration.superclass {
+                    if !matches!(self.lookup(superclass), Type::Class(_) | Type::Any) {
+                        self.error(
+                            superclass,
+                            format!("'{}' is not a class.", superclass.lexeme),
+                        );
+                    }
+                }
+                self.scopes.push(HashMap::new());
+                let this = Type::Instance(declaration.name.lexeme.clone());
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(String::from("this"), this);
+                if declaration.superclass.is_some() {
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .insert(String::from("super"), Type::Any);
+                }
+                for method in declaration.methods.iter() {
+                    if let Type::Function(Some(signature)) = self.signature(method) {
+                        self.check_function(method, &signature);
+                    }
+                }
+                self.scopes.pop();
+            }
+            Stmt::Return(keyword, value) => {
+                let value_type = match value {
+                    Some(value) => self.check_expr(value),
+                    None => Type::Nil,
+                };
+                match self.return_types.last().cloned() {
+                    Some(expected) if !expected.accepts(&value_type, &self.superclasses) => {
+                        let message = format!(
+                            "Cannot return a value of type {} from a function returning {}.",
+                            value_type, expected
+                        );
+                        match value {
+                            Some(value) => self.mismatch(keyword, message, value, &value_type),
+                            None => self.error(keyword, message),
+                        }
+                    }
+                    Some(_) => {}
+                    None => self.error(keyword, String::from("Cannot return from top-level code.")),
+                }
+            }
+            Stmt::Enum(declaration) => {
+                self.declare(&declaration.name, Type::Any);
+            }
+            Stmt::Match(_, scrutinee, arms) => {
+                self.check_expr(scrutinee);
+                for arm in arms.iter() {
+                    self.scopes.push(HashMap::new());
+                    if let Pattern::Variant {
+                        bindings: Some(bindings),
+                        ..
+                    } = &arm.pattern
+                    {
+                        for binding in bindings.iter() {
+                            self.declare(binding, Type::Any);
+                        }
+                    }
+                    self.check_stmt(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(token) => match token.token_type {
+                TokenType::Number(_) => Type::Number,
+                TokenType::String(_) => Type::String,
+                TokenType::True | TokenType::False => Type::Boolean,
+                TokenType::Nil => Type::Nil,
+                _ => Type::Any,
+            },
+            Expr::Grouping(_, expr) => self.check_expr(expr),
+            Expr::Var(name) => self.lookup(name),
+            Expr::Assignment(name, value) => {
+                let value_type = self.check_expr(value);
+                let declared = self.lookup(name);
+                if !declared.accepts(&value_type, &self.superclasses) {
+                    self.mismatch(
+                        name,
+                        format!(
+                            "Cannot assign a value of type {} to '{}' of type {}.",
+                            value_type, name.lexeme, declared
+                        ),
+                        value,
+                        &value_type,
+                    );
+                }
+                value_type
+            }
+            Expr::Unary { right, op } => {
+                let right = self.check_expr(right);
+                match op.token_type {
+                    TokenType::Minus if matches!(right, Type::Instance(_) | Type::Any) => Type::Any,
+                    TokenType::Minus => {
+                        if !Type::Number.accepts(&right, &self.superclasses) {
+                            self.error(op, format!("Operand of '-' must be num, found {}.", right));
+                        }
+                        Type::Number
+                    }
+                    _ => Type::Boolean,
+                }
+            }
+            Expr::Get { object, .. } => {
+                self.check_expr(object);
+                Type::Any
+            }
+            Expr::Set { object, value, .. } => {
+                self.check_expr(object);
+                self.check_expr(value)
+            }
+            Expr::This(keyword) => self.lookup(keyword),
+            Expr::Super { .. } => Type::Any,
+            Expr::Binary { left, op, right } => {
+                let left = self.check_expr(left);
+                let right = self.check_expr(right);
+                self.check_binary(op, left, right)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                named,
+            } => {
+                let call = callee.span().to(paren.span());
+                let callee = self.check_expr(callee);
+                let mut argument_types = vec![];
+                for argument in arguments.iter() {
+                    argument_types.push((argument.span(), self.check_expr(argument)));
+                }
+                let mut named_types = vec![];
+                for (name, argument) in named.iter() {
+                    named_types.push((name, argument.span(), self.check_expr(argument)));
+                }
+                match callee {
+                    Type::Function(Some(signature)) => {
+                        self.check_call(call, &signature, argument_types, named_types);
+                        signature.returns.clone()
+                    }
+                    Type::Function(None) | Type::Any => Type::Any,
+                    Type::Class(name) => Type::Instance(name),
+                    other => {
+                        self.error(paren, format!("Can only call functions, found {}.", other));
+                        Type::Any
+                    }
+                }
+            }
+            Expr::List { elements, .. } => {
+                for element in elements.iter() {
+                    self.check_expr(element);
+                }
+                Type::List
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+                ..
+            } => match self.check_list_index(bracket, object, index) {
+                Type::String => Type::String,
+                _ => Type::Any,
+            },
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                if self.check_list_index(bracket, object, index) == Type::String {
+                    self.error(bracket, String::from("Strings are immutable."));
+                }
+                self.check_expr(value)
+            }
+        }
+    }
+
+    /// Checks an index into a list, string or map, returning the type of the
+    /// indexed object.
+    fn check_list_index(&mut self, bracket: &Token, object: &Expr, index: &Expr) -> Type {
+        let object_type = self.check_expr(object);
+        let index_type = self.check_expr(index);
+        if matches!(object_type, Type::Instance(_)) {
+            // Instances define their own indexing through `__index__`.
+            return object_type;
+        }
+        let expected = match object_type {
+            Type::Any => Type::Any,
+            Type::List | Type::String => Type::Number,
+            Type::Map => Type::String,
+            _ => {
+                self.error(
+                    bracket,
+                    format!(
+                        "Only lists, strings and maps can be indexed, found {}.",
+                        object_type
+                    ),
+                );
+                Type::Any
+            }
+        };
+        if !expected.accepts(&index_type, &self.superclasses) {
+            self.error(
+                bracket,
+                format!("Index must be {}, found {}.", expected, index_type),
+            );
+        }
+        return object_type;
+    }
+
+    /// Checks the arguments of the call spanning `call` against `signature`,
+    /// reporting a mismatched argument at the argument itself.
+    fn check_call(
+        &mut self,
+        call: Span,
+        signature: &Signature,
+        arguments: Vec<(Span, Type)>,
+        named: Vec<(&Token, Span, Type)>,
+    ) {
+        let mut filled = vec![false; signature.params.len()];
+        if arguments.len() > signature.params.len() && !signature.rest {
+            self.error_at(
+                call,
+                format!(
+                    "Expected at most {} but got {}.",
+                    argument_count(signature.params.len()),
+                    arguments.len()
+                ),
+            );
+        }
+        for (i, (param, (span, argument))) in
+            signature.params.iter().zip(arguments.iter()).enumerate()
+        {
+            filled[i] = true;
+            if !param.param_type.accepts(argument, &self.superclasses) {
+                self.error_at(
+                    *span,
+                    format!(
+                        "Argument '{}' must be {}, found {}.",
+                        param.name, param.param_type, argument
+                    ),
+                );
+            }
+        }
+        for (name, span, argument) in named {
+            match signature
+                .params
+                .iter()
+                .position(|param| param.name == name.lexeme)
+            {
+                Some(i) if filled[i] => {
+                    self.error(name, format!("Multiple values for '{}'.", name.lexeme))
+                }
+                Some(i) => {
+                    filled[i] = true;
+                    let param = &signature.params[i];
+                    if !param.param_type.accepts(&argument, &self.superclasses) {
+                        self.error_at(
+                            name.span().to(span),
+                            format!(
+                                "Argument '{}' must be {}, found {}.",
+                                param.name, param.param_type, argument
+                            ),
+                        );
+                    }
+                }
+                None => self.error(name, format!("No parameter named '{}'.", name.lexeme)),
+            }
+        }
+        for (param, filled) in signature.params.iter().zip(filled) {
+            if !filled && !param.optional {
+                self.error_at(call, format!("Missing argument '{}'.", param.name));
+            }
+        }
+    }
+
+    fn check_binary(&mut self, op: &Token, left: Type, right: Type) -> Type {
+        let overloadable = |operand: &Type| matches!(operand, Type::Instance(_) | Type::Any);
+        if overloadable(&left) || overloadable(&right) {
+            // Either side may be an instance overloading the operator, which
+            // may return anything.
+            return match op.token_type {
+                TokenType::EqualEqual | TokenType::BangEqual => Type::Boolean,
+                _ => Type::Any,
+            };
+        }
+        match op.token_type {
+            TokenType::Plus => match (&left, &right) {
+                (Type::Number, Type::Number) => Type::Number,
+                (Type::String, Type::String) => Type::String,
+                _ => {
+                    self.error(
+                        op,
+                        format!(
+                            "Operands of '+' must be two numbers or two strings, found {} and {}.",
+                            left, right
+                        ),
+                    );
+                    Type::Any
+                }
+            },
+            TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual => {
+                if !Type::Number.accepts(&left, &self.superclasses)
+                    || !Type::Number.accepts(&right, &self.superclasses)
+                {
+                    self.error(
+                        op,
+                        format!(
+                            "Operands of '{}' must be numbers, found {} and {}.",
+                            op.lexeme, left, right
+                        ),
+                    );
+                }
+                if matches!(
+                    op.token_type,
+                    TokenType::Minus | TokenType::Star | TokenType::Slash
+                ) {
+                    Type::Number
+                } else {
+                    Type::Boolean
+                }
+            }
+            _ => Type::Boolean,
+        }
+    }
+
+    fn check_function(&mut self, declaration: &FunctionDecl, signature: &Signature) {
+        self.scopes.push(HashMap::new());
+        for (param, param_type) in declaration.params.iter().zip(&signature.params) {
+            if let Some(default) = &param.default {
+                let default_type = self.check_expr(default);
+                if !param_type
+                    .param_type
+                    .accepts(&default_type, &self.superclasses)
+                {
+                    self.mismatch(
+                        &param.name,
+                        format!(
+                            "Default for '{}' of type {} has type {}.",
+                            param.name.lexeme, param_type.param_type, default_type
+                        ),
+                        default,
+                        &default_type,
+                    );
+                }
+            }
+            self.declare(&param.name, param_type.param_type.clone());
+        }
+        if let Some(rest) = declaration.params.last().filter(|param| param.rest) {
+            self.declare(&rest.name, Type::List);
+        }
+        self.return_types.push(signature.returns.clone());
+        self.check_block(&declaration.body);
+        self.return_types.pop();
+        self.scopes.pop();
+    }
+
+    fn signature(&mut self, declaration: &FunctionDecl) -> Type {
+        let mut params = vec![];
+        let mut rest = false;
+        for param in declaration.params.iter() {
+            if param.rest {
+                rest = true;
+                if !Type::List.accepts(&self.resolve(&param.annotation), &self.superclasses) {
+                    self.error(&param.name, String::from("Rest parameter must be a list."));
+                }
+                continue;
+            }
+            params.push(ParamType {
+                name: param.name.lexeme.clone(),
+                param_type: self.resolve(&param.annotation),
+                optional: param.default.is_some(),
+            });
+        }
+        let mut returns = self.resolve(&declaration.return_type);
+        if declaration.generator {
+            if let Some(return_type) = &declaration.return_type {
+                self.error(
+                    return_type,
+                    String::from("Generator functions cannot declare a return type."),
+                );
+            }
+            returns = Type::Any;
+        }
+        return Type::Function(Some(Rc::new(Signature {
+            params,
+            rest,
+            returns,
+        })));
+    }
+
+    fn resolve(&mut self, annotation: &Option<Token>) -> Type {
+        match annotation {
+            Some(annotation) => match Type::from_annotation(annotation) {
+                Some(resolved) => resolved,
+                None if matches!(self.lookup(annotation), Type::Class(_)) => {
+                    Type::Instance(annotation.lexeme.clone())
+                }
+                None => {
+                    self.errors.push(TypeError {
+                        help: Some(String::from(
+                            "The types are any, num, str, bool, nil, list, map, fun and class names.",
+                        )),
+                        ..TypeError::new(annotation, format!("Unknown type '{}'.", annotation.lexeme))
+                    });
+                    Type::Any
+                }
+            },
+            None => Type::Any,
+        }
+    }
+
+    fn declare(&mut self, name: &Token, declared: Type) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.lexeme.clone(), declared);
+    }
+
+    fn lookup(&self, name: &Token) -> Type {
+        for scope in self.scopes.iter().rev() {
+            if let Some(found) = scope.get(&name.lexeme) {
+                return found.clone();
+            }
+        }
+        return Type::Any;
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.errors.push(TypeError::new(token, message));
+    }
+
+    fn error_at(&mut self, span: Span, message: String) {
+        self.errors.push(TypeError::spanning(span, message));
+    }
+
+    /// An error at `token` about `value` having the wrong type, with `value`
+    /// labelled with the type it was `found` to have.
+    fn mismatch(&mut self, token: &Token, message: String, value: &Expr, found: &Type) {
+        let mut error = TypeError::new(token, message);
+        error.labels.push(Label {
+            span: value.span(),
+            message: format!("this is {}", found),
+        });
+        self.errors.push(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::{parser::Parser, scanner::Scanner};
+
+    /// Each type error in `source` as its line and message.
+    fn check(source: &str) -> Vec<(usize, String)> {
+        let (tokens, _) = Scanner::new(String::from(source), 0).scan_tokens();
+        let program = Parser::new(tokens).parse().ok().unwrap();
+        return Checker::new()
+            .check(&program)
+            .into_iter()
+            .map(|error| (error.span.line, error.message))
+            .collect();
+    }
+
+    #[test]
+    fn reports_each_bad_argument_at_the_argument() {
+        let source = "fun f(a: num, b: num) {}\nf(\"x\", \"y\");\nf(1, 2, 3);";
+        let (tokens, _) = Scanner::new(String::from(source), 0).scan_tokens();
+        let program = Parser::new(tokens).parse().ok().unwrap();
+        let spans: Vec<(usize, usize, usize)> = Checker::new()
+            .check(&program)
+            .iter()
+            .map(|error| {
+                (
+                    error.span.line,
+                    error.span.column,
+                    error.span.end - error.span.start,
+                )
+            })
+            .collect();
+        assert_eq!(spans, vec![(2, 3, 3), (2, 8, 3), (3, 1, 10)]);
+    }
+
+    #[test]
+    fn accepts_a_subclass_where_its_superclass_is_expected() {
+        let source = "class Animal {}\n\
+                      class Dog < Animal {}\n\
+                      class Puppy < Dog {}\n\
+                      var a: Animal = Puppy();\n\
+                      fun greet(x: Animal) {}\n\
+                      greet(Dog());\n\
+                      greet(Puppy());";
+        assert_eq!(check(source), vec![]);
+    }
+
+    #[test]
+    fn refuses_a_superclass_or_unrelated_class() {
+        let source = "class Animal {}\n\
+                      class Dog < Animal {}\n\
+                      class Cat {}\n\
+                      var d: Dog = Animal();\n\
+                      fun greet(x: Dog) {}\n\
+                      greet(Cat());";
+        let lines: Vec<usize> = check(source).iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![4, 6]);
+    }
+
+    #[test]
+    fn stops_at_classes_that_inherit_in_a_loop() {
+        let source = "class A < B {}\nclass B < A {}\nclass C {}\nvar c: C = A();";
+        assert_eq!(check(source).len(), 1);
+    }
+}
@@ -0,0 +1,567 @@
+use crate::lox::{
+    checker::TypeError,
+    interpreter::{RuntimeError, Value},
+    json,
+    parser::ParseError,
+    tokens::Span,
+    Output,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// How serious a diagnostic is. Errors stop a program from running, or
+/// stopped it part way; warnings leave it to run.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Another place a diagnostic points at, such as where a variable was
+/// declared, with what to say about it.
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// One problem found in a program and where it was found.
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// What kind of problem this is, such as `unused-variable`, for the
+    /// problems that can be told apart or silenced.
+    pub code: Option<&'static str>,
+    pub message: String,
+    /// The source the problem covers, when it is known.
+    pub span: Option<Span>,
+    /// The text the problem was found at, such as an unexpected token. An
+    /// empty string stands for the end of the source.
+    pub near: Option<String>,
+    /// The file the problem is in, or `None` for the program itself. When
+    /// the span's source is known, its file is shown instead.
+    pub file: Option<String>,
+    /// Other places in the same source that explain the problem.
+    pub labels: Vec<Label>,
+    /// Further detail, shown after the source.
+    pub notes: Vec<String>,
+    /// A suggestion for fixing the problem.
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Option<Span>, message: String) -> Diagnostic {
+        return Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message,
+            span,
+            near: None,
+            file: None,
+            labels: vec![],
+            notes: vec![],
+            help: None,
+        };
+    }
+
+    pub fn warning(span: Option<Span>, message: String) -> Diagnostic {
+        return Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(span, message)
+        };
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        return self;
+    }
+
+    pub fn with_label(mut self, span: Span, message: String) -> Diagnostic {
+        self.labels.push(Label { span, message });
+        return self;
+    }
+
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        return self;
+    }
+
+    pub fn with_help(mut self, help: String) -> Diagnostic {
+        self.help = Some(help);
+        return self;
+    }
+}
+
+/// The one line summary, without the source.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        if let Some(span) = self.span {
+            write!(f, "[line {}:{}] ", span.line, span.column)?;
+        }
+        match self.severity {
+            Severity::Error => write!(f, "Error")?,
+            Severity::Warning => write!(f, "Warning")?,
+        }
+        match &self.near {
+            Some(near) if near.is_empty() => write!(f, " at end")?,
+            Some(near) => write!(f, " at '{}'", near)?,
+            None => {}
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Diagnostic {
+        let span = error.token.span();
+        return Diagnostic {
+            code: Some("syntax-error"),
+            near: Some(error.token.lexeme),
+            ..Diagnostic::error(Some(span), error.message)
+        };
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(error: TypeError) -> Diagnostic {
+        return Diagnostic {
+            code: Some("type-error"),
+            near: error.near,
+            labels: error.labels,
+            help: error.help,
+            ..Diagnostic::error(Some(error.span), error.message)
+        };
+    }
+}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Diagnostic {
+        return Diagnostic {
+            code: Some("runtime-error"),
+            help: error.help,
+            ..Diagnostic::error(error.span, error.message)
+        };
+    }
+}
+
+/// Collects the diagnostics from scanning, parsing, checking and running a
+/// program, leaving it to the embedder to show them: the command line
+/// writes them to standard error and the wasm build returns them with the
+/// program's output.
+#[derive(Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    /// Each source scanned, so diagnostics can be shown with the lines they
+    /// point at.
+    sources: Vec<Source>,
+    /// The number the next source added is given. Sinks made with `nested`
+    /// carry on from their parent's count, so spans from the prelude, a
+    /// program, or lines typed at the prompt never mistake one source for
+    /// another once `extend_from_file` brings them together.
+    next_source: usize,
+}
+
+/// The text of a source, the number its spans carry, and the file it came
+/// from, or `None` for the program itself.
+struct Source {
+    id: usize,
+    file: Option<String>,
+    text: String,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        return Diagnostics::default();
+    }
+
+    /// An empty sink for the diagnostics of one file, numbering its sources
+    /// after those added here so far. It is meant to be handed back with
+    /// `extend_from_file` before anything else is added here.
+    pub fn nested(&self) -> Diagnostics {
+        return Diagnostics {
+            next_source: self.next_source,
+            ..Diagnostics::default()
+        };
+    }
+
+    pub fn report<D: Into<Diagnostic>>(&mut self, diagnostic: D) {
+        self.diagnostics.push(diagnostic.into());
+    }
+
+    pub fn has_errors(&self) -> bool {
+        return self
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.diagnostics.is_empty();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        return self.diagnostics.iter();
+    }
+
+    /// Removes and returns everything collected so far.
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        return std::mem::take(&mut self.diagnostics);
+    }
+
+    /// Remembers the text of a new source from `file`, returning the number
+    /// its spans are to carry.
+    pub fn add_source(&mut self, file: Option<String>, text: String) -> usize {
+        let id = self.next_source;
+        self.next_source += 1;
+        self.sources.push(Source { id, file, text });
+        return id;
+    }
+
+    /// Moves everything in `found` here, marking what it found in the
+    /// program itself as found in `file`. A diagnostic in another source
+    /// known here, such as a runtime error raised in the prelude, keeps
+    /// that source's file.
+    pub fn extend_from_file(&mut self, file: &str, mut found: Diagnostics) {
+        self.next_source = self.next_source.max(found.next_source);
+        for mut source in found.sources.drain(..) {
+            source.file = source.file.or_else(|| Some(String::from(file)));
+            self.sources.push(source);
+        }
+        for mut diagnostic in found.take() {
+            if diagnostic.file.is_none() {
+                let known = self.file(&diagnostic).unwrap_or(file);
+                diagnostic.file = Some(String::from(known));
+            }
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// The file `diagnostic` is in: that of the source its span is in, when
+    /// it is known, or else the one it was marked with.
+    fn file<'a>(&'a self, diagnostic: &'a Diagnostic) -> Option<&'a str> {
+        return diagnostic
+            .span
+            .and_then(|span| self.source(span))
+            .and_then(|source| source.file.as_deref())
+            .or(diagnostic.file.as_deref());
+    }
+
+    fn source(&self, span: Span) -> Option<&Source> {
+        return self.sources.iter().find(|source| source.id == span.source);
+    }
+
+    /// `diagnostic` as a single line of JSON, for tools to read, as
+    /// `--error-format=json` writes it. Lines and columns count from 1, and
+    /// the end column is just past the last character covered.
+    pub fn to_json(&self, diagnostic: &Diagnostic) -> String {
+        let value = self.json_value(diagnostic);
+        return json::stringify(&value, "").unwrap_or_default();
+    }
+
+    /// The program's `output` and every diagnostic as one JSON object with
+    /// `output`, `error_output` and `diagnostics` fields, as the wasm build
+    /// returns them.
+    pub fn to_json_report(&self, output: &Output) -> String {
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| self.json_value(diagnostic))
+            .collect();
+        let report = object(vec![
+            ("output", Value::String(output.text.clone())),
+            ("error_output", Value::String(output.error_text.clone())),
+            ("diagnostics", list(diagnostics)),
+        ]);
+        return json::stringify(&report, "").unwrap_or_default();
+    }
+
+    fn json_value(&self, diagnostic: &Diagnostic) -> Value {
+        let text = |text: &Option<String>| match text {
+            Some(text) => Value::String(text.clone()),
+            None => Value::Nil,
+        };
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let labels = diagnostic
+            .labels
+            .iter()
+            .map(|label| {
+                let mut fields = vec![("message", Value::String(label.message.clone()))];
+                fields.extend(self.json_location(Some(label.span)));
+                object(fields)
+            })
+            .collect();
+        let notes = diagnostic
+            .notes
+            .iter()
+            .map(|note| Value::String(note.clone()))
+            .collect();
+        let mut fields = vec![
+            ("severity", Value::String(String::from(severity))),
+            ("code", text(&diagnostic.code.map(String::from))),
+            ("message", Value::String(diagnostic.message.clone())),
+            ("file", text(&self.file(diagnostic).map(String::from))),
+            ("labels", list(labels)),
+            ("notes", list(notes)),
+            ("help", text(&diagnostic.help)),
+        ];
+        fields.extend(self.json_location(diagnostic.span));
+        return object(fields);
+    }
+
+    /// The start and end lines and columns of `span`, all `null` when there
+    /// is no span.
+    fn json_location(&self, span: Option<Span>) -> Vec<(&'static str, Value)> {
+        let number = |n: usize| Value::Number(n as f32);
+        let (start, end) = match span {
+            Some(span) => {
+                let end = match self.source(span) {
+                    Some(source) => position(&source.text, span.end),
+                    None => (span.line, span.column),
+                };
+                (
+                    (number(span.line), number(span.column)),
+                    (number(end.0), number(end.1)),
+                )
+            }
+            None => ((Value::Nil, Value::Nil), (Value::Nil, Value::Nil)),
+        };
+        return vec![
+            ("start_line", start.0),
+            ("start_column", start.1),
+            ("end_line", end.0),
+            ("end_column", end.1),
+        ];
+    }
+
+    /// Shows `diagnostic` with the source lines it points at marked
+    /// underneath, and its notes and help after them. `colour` adds
+    /// terminal colour codes; without it the text is plain, as the wasm
+    /// build returns it.
+    pub fn render(&self, diagnostic: &Diagnostic, colour: bool) -> String {
+        let style = Style { colour };
+        let mut severity = String::from(match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        });
+        if let Some(code) = diagnostic.code {
+            severity = format!("{}[{}]", severity, code);
+        }
+        let severity_code = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut out = format!(
+            "{}{}\n",
+            style.paint(severity_code, &severity),
+            style.paint(BOLD, &format!(": {}", diagnostic.message))
+        );
+        let span = match diagnostic.span {
+            Some(span) => span,
+            None => {
+                if let Some(file) = self.file(diagnostic) {
+                    let _ = writeln!(out, "{} {}", style.paint(BLUE, "-->"), file);
+                }
+                render_notes(diagnostic, &style, 0, &mut out);
+                return out;
+            }
+        };
+
+        let mut marks = vec![(span, '^', severity_code, None)];
+        // Labels are only drawn in the snippet of the diagnostic's own source.
+        for label in diagnostic
+            .labels
+            .iter()
+            .filter(|label| label.span.source == span.source)
+        {
+            marks.push((label.span, '-', BLUE, Some(&label.message)));
+        }
+        let width = marks
+            .iter()
+            .map(|(span, ..)| span.line.to_string().len())
+            .max()
+            .unwrap();
+        let gutter = " ".repeat(width);
+
+        let location = match self.file(diagnostic) {
+            Some(file) => format!("{}:{}:{}", file, span.line, span.column),
+            None => format!("{}:{}", span.line, span.column),
+        };
+        let _ = writeln!(out, "{}{} {}", gutter, style.paint(BLUE, "-->"), location);
+        if let Some(source) = self.source(span).map(|source| &source.text[..]) {
+            let _ = writeln!(out, "{} {}", gutter, style.paint(BLUE, "|"));
+            let mut lines: Vec<usize> = marks.iter().map(|(span, ..)| span.line).collect();
+            lines.sort_unstable();
+            lines.dedup();
+            for line in lines {
+                let text = source.lines().nth(line - 1).unwrap_or("");
+                let number = format!("{:>width$} |", line, width = width);
+                let _ = writeln!(out, "{} {}", style.paint(BLUE, &number), text);
+                let mut on_line: Vec<_> = marks.iter().filter(|mark| mark.0.line == line).collect();
+                on_line.sort_by_key(|mark| mark.0.column);
+                // Marks share a row while they do not overlap, until one
+                // has a message trailing after it.
+                let mut rows: Vec<(String, usize, bool)> = vec![];
+                for (span, marker, code, message) in on_line {
+                    let column = span.column - 1;
+                    let length = source
+                        .get(span.start..span.end)
+                        .map(|marked| marked.lines().next().unwrap_or("").chars().count())
+                        .unwrap_or(0)
+                        .max(1);
+                    let mut underline = marker.to_string().repeat(length);
+                    if let Some(message) = message {
+                        underline = format!("{} {}", underline, message);
+                    }
+                    match rows.last_mut() {
+                        Some((row, end, false)) if *end <= column => {
+                            row.push_str(&indent(text, *end, column));
+                            row.push_str(&style.paint(code, &underline));
+                            *end = column + length;
+                        }
+                        _ => rows.push((
+                            indent(text, 0, column) + &style.paint(code, &underline),
+                            column + length,
+                            false,
+                        )),
+                    }
+                    rows.last_mut().unwrap().2 = message.is_some();
+                }
+                for (row, _, _) in rows {
+                    let _ = writeln!(
+                        out,
+                        "{} {}",
+                        style.paint(BLUE, &format!("{} |", gutter)),
+                        row
+                    );
+                }
+            }
+        }
+        render_notes(diagnostic, &style, width, &mut out);
+        return out;
+    }
+}
+
+fn render_notes(diagnostic: &Diagnostic, style: &Style, width: usize, out: &mut String) {
+    let gutter = " ".repeat(width);
+    for note in diagnostic.notes.iter() {
+        let _ = writeln!(out, "{} {} {}", gutter, style.paint(BOLD, "= note:"), note);
+    }
+    if let Some(help) = &diagnostic.help {
+        let _ = writeln!(out, "{} {} {}", gutter, style.paint(BOLD, "= help:"), help);
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, diagnostics: I) {
+        self.diagnostics.extend(diagnostics);
+    }
+}
+
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+
+/// The line and column of byte `offset` in `source`.
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = source.get(..offset).unwrap_or(source);
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let line = before.matches('\n').count() + 1;
+    return (line, before[line_start..].chars().count() + 1);
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    let fields: BTreeMap<String, Value> = fields
+        .into_iter()
+        .map(|(key, value)| (String::from(key), value))
+        .collect();
+    return Value::Map(Rc::new(RefCell::new(fields)));
+}
+
+fn list(elements: Vec<Value>) -> Value {
+    return Value::List(Rc::new(RefCell::new(elements)));
+}
+
+/// Blank space taking up the same room as characters `from..to` of `text`,
+/// keeping its tabs so marks line up however wide a tab is shown.
+fn indent(text: &str, from: usize, to: usize) -> String {
+    return text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .skip(from)
+        .take(to - from)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+}
+
+/// Wraps text in ANSI colour codes when colour is on.
+struct Style {
+    colour: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if !self.colour {
+            return String::from(text);
+        }
+        return format!("\x1b[{}m{}\x1b[0m", code, text);
+    }
+}
+
+/// Whether diagnostics written to standard error should be coloured: only
+/// when it is a terminal and `NO_COLOR` is not set.
+pub fn colour_stderr() -> bool {
+    use std::io::IsTerminal;
+    return std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_in(source: usize) -> Diagnostic {
+        let span = Span {
+            source,
+            start: 0,
+            end: 1,
+            line: 1,
+            column: 1,
+        };
+        return Diagnostic::error(Some(span), String::from("Oops."));
+    }
+
+    #[test]
+    fn numbers_sources_separately_in_each_sink() {
+        assert_eq!(Diagnostics::new().add_source(None, String::from("a")), 0);
+        assert_eq!(Diagnostics::new().add_source(None, String::from("b")), 0);
+    }
+
+    #[test]
+    fn keeps_nested_sources_apart_once_merged() {
+        let mut diagnostics = Diagnostics::new();
+        let prelude = diagnostics.add_source(Some(String::from("prelude")), String::from("a"));
+        let mut found = diagnostics.nested();
+        let program = found.add_source(None, String::from("b"));
+        assert_ne!(prelude, program);
+        found.report(error_in(prelude));
+        found.report(error_in(program));
+        diagnostics.extend_from_file("main.lox", found);
+        let files: Vec<_> = diagnostics.iter().map(|d| d.file.clone()).collect();
+        assert_eq!(
+            files,
+            vec![
+                Some(String::from("prelude")),
+                Some(String::from("main.lox"))
+            ]
+        );
+        let next = diagnostics.add_source(None, String::from("c"));
+        assert!(next != prelude && next != program);
+    }
+}
@@ -1,10 +1,12 @@
-use crate::lox::interpreter::Value;
+use crate::lox::interpreter::{RuntimeError, Value};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Environment {
     variables: HashMap<String, Value>,
-    pub parent: Option<Box<Environment>>,
+    pub parent: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
@@ -15,33 +17,103 @@ impl Environment {
         };
     }
 
+    pub fn with_parent(parent: Rc<RefCell<Environment>>) -> Environment {
+        return Environment {
+            variables: HashMap::new(),
+            parent: Some(parent),
+        };
+    }
+
     pub fn set_variable(&mut self, identifier: String, value: Value) {
         self.variables.insert(identifier, value);
     }
 
-    pub fn get_variable(&self, identifier: String) -> &Value {
-        self.print();
-        if self.variables.contains_key(&identifier) {
-            return self.variables.get(&identifier).unwrap();
+    pub fn get_variable(&self, identifier: String) -> Result<Value, RuntimeError> {
+        match self.lookup(&identifier) {
+            Some(value) => Ok(value),
+            None => Err(self.undefined(&identifier)),
         }
-        if matches!(self.parent, Some(_)) {
-            return self.parent.as_ref().unwrap().get_variable(identifier);
+    }
+
+    fn lookup(&self, identifier: &str) -> Option<Value> {
+        if let Some(value) = self.variables.get(identifier) {
+            return Some(value.clone());
         }
-        return &Value::Nil;
+        return self
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.borrow().lookup(identifier));
     }
 
-    pub fn assign_variable(&mut self, identifier: String, value: Value) {
-        if self.variables.contains_key(&identifier) {
-            self.variables.insert(identifier, value);
-            return;
+    pub fn assign_variable(
+        &mut self,
+        identifier: String,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        if self.assign(&identifier, value) {
+            return Ok(());
+        }
+        return Err(self.undefined(&identifier));
+    }
+
+    fn assign(&mut self, identifier: &str, value: Value) -> bool {
+        if let Some(variable) = self.variables.get_mut(identifier) {
+            *variable = value;
+            return true;
+        }
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().assign(identifier, value),
+            None => false,
+        }
+    }
+
+    /// The error for a name not defined in this scope or any enclosing it,
+    /// suggesting the closest name that is, if one is close enough to be a
+    /// likely typo. Of names equally close, the one in the nearest scope is
+    /// suggested.
+    fn undefined(&self, identifier: &str) -> RuntimeError {
+        let mut error = RuntimeError::new(format!("Undefined variable '{}'.", identifier));
+        let mut names = vec![];
+        self.names(&mut names);
+        let closest = names
+            .into_iter()
+            .map(|name| (edit_distance(identifier, &name), name))
+            .filter(|(distance, _)| *distance <= identifier.chars().count().div_ceil(3))
+            .min_by_key(|(distance, _)| *distance);
+        if let Some((_, name)) = closest {
+            error.help = Some(format!("Did you mean '{}'?", name));
+        }
+        return error;
+    }
+
+    /// Adds every name visible from this scope to `names`, innermost scope
+    /// first and each scope's names in order.
+    fn names(&self, names: &mut Vec<String>) {
+        let mut own: Vec<String> = self.variables.keys().cloned().collect();
+        own.sort();
+        names.extend(own);
+        if let Some(parent) = &self.parent {
+            parent.borrow().names(names);
         }
-        match &mut self.parent {
-            Some(parent) => parent.assign_variable(identifier, value),
-            _ => panic!(""),
-        };
     }
 
     pub fn print(&self) {
         println!("{:?}", self.variables);
     }
 }
+
+/// The Levenshtein distance between `a` and `b`: how many characters must be
+/// inserted, removed or replaced to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let replace = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    return previous[b.len()];
+}
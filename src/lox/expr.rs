@@ -1,4 +1,4 @@
-use crate::lox::tokens::Token;
+use crate::lox::tokens::{Span, Token};
 use std::fmt;
 
 pub enum Expr {
@@ -7,7 +7,7 @@ pub enum Expr {
         op: Token,
         right: Box<Expr>,
     },
-    Grouping(Box<Expr>),
+    Grouping(Span, Box<Expr>),
     Literal(Token),
     Unary {
         right: Box<Expr>,
@@ -15,12 +15,49 @@ pub enum Expr {
     },
     Var(Token),
     Assignment(Token, Box<Expr>),
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+        named: Vec<(Token, Expr)>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This(Token),
+    Super {
+        keyword: Token,
+        method: Token,
+    },
+    List {
+        bracket: Token,
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        span: Span,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Expr::Grouping(expr) => write!(f, "(group {})", expr),
+            Expr::Grouping(_, expr) => write!(f, "(group {})", expr),
             Expr::Binary { left, right, op } => {
                 write!(f, "({} {} {})", op.lexeme, left, right)
             }
@@ -28,18 +65,72 @@ impl fmt::Display for Expr {
             Expr::Unary { right, op } => write!(f, "({} {})", op.lexeme, right),
             Expr::Var(token) => write!(f, "Var {}", token),
             Expr::Assignment(token, right) => write!(f, "Assignment {} = {}", token.lexeme, right),
+            Expr::Call {
+                callee,
+                arguments,
+                named,
+                ..
+            } => {
+                write!(f, "(call {}", callee)?;
+                for argument in arguments.iter() {
+                    write!(f, " {}", argument)?;
+                }
+                for (name, argument) in named.iter() {
+                    write!(f, " {}: {}", name.lexeme, argument)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Get { object, name } => write!(f, "(get {} {})", object, name.lexeme),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => write!(f, "(set {} {} {})", object, name.lexeme, value),
+            Expr::This(_) => write!(f, "this"),
+            Expr::Super { method, .. } => write!(f, "(super {})", method.lexeme),
+            Expr::List { elements, .. } => {
+                write!(f, "(list")?;
+                for element in elements.iter() {
+                    write!(f, " {}", element)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Index { object, index, .. } => write!(f, "(index {} {})", object, index),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => write!(f, "(set-index {} {} {})", object, index, value),
         }
     }
 }
 
 impl Expr {
+    /// The source the expression covers, from its first token to its last.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary { left, right, .. } => left.span().to(right.span()),
+            Expr::Grouping(span, _) | Expr::List { span, .. } | Expr::Index { span, .. } => *span,
+            Expr::Literal(token) | Expr::Var(token) | Expr::This(token) => token.span(),
+            Expr::Unary { right, op } => op.span().to(right.span()),
+            Expr::Assignment(name, value) => name.span().to(value.span()),
+            Expr::Call { callee, paren, .. } => callee.span().to(paren.span()),
+            Expr::Get { object, name } => object.span().to(name.span()),
+            Expr::Set { object, value, .. } | Expr::SetIndex { object, value, .. } => {
+                object.span().to(value.span())
+            }
+            Expr::Super { keyword, method } => keyword.span().to(method.span()),
+        }
+    }
+
     pub fn print_rpn(&self) -> String {
         match self {
-            Expr::Grouping(expr) => format!("{}", expr.print_rpn()),
+            Expr::Grouping(_, expr) => expr.print_rpn(),
             Expr::Binary { left, right, op } => {
                 format!("{} {} {}", left.print_rpn(), right.print_rpn(), op.lexeme)
             }
-            Expr::Literal(literal) => format!("{}", literal.lexeme),
+            Expr::Literal(literal) => literal.lexeme.clone(),
             Expr::Unary { right, op } => format!("{} {}", right.print_rpn(), op.lexeme),
             _ => panic!("Unsupported expression"),
         }
@@ -0,0 +1,250 @@
+use crate::lox::{
+    interpreter::{RuntimeError, Value},
+    Output,
+};
+use std::collections::BTreeMap;
+
+/// The largest width or precision a placeholder may ask for.
+const MAX_WIDTH: usize = 1000;
+
+/// Fills the placeholders in `template` from the arguments of `format()`.
+///
+/// A placeholder is `{argument:spec}`, where both parts are optional. The
+/// argument is a position, a name given as a named argument, or empty for
+/// the next positional argument. The spec is `[[fill]align][width][.precision]`:
+/// `align` is `<`, `>` or `^` and pads with `fill`, a space by default, up to
+/// `width` characters. Numbers are aligned right and everything else left
+/// unless told otherwise. Precision is the number of decimal places for a
+/// number and the most characters to show for anything else. Width and
+/// precision are at most 1000. `{{` and `}}` stand for literal braces.
+pub fn format(
+    template: &str,
+    positional: &[Value],
+    named: &BTreeMap<String, Value>,
+    output: &mut Output,
+) -> Result<String, RuntimeError> {
+    let mut text = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '}' => return Err(error("Unmatched '}' in format string.")),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(error("Unclosed '{' in format string.")),
+                    }
+                }
+                let (argument, spec) = match placeholder.find(':') {
+                    Some(colon) => (&placeholder[..colon], &placeholder[colon + 1..]),
+                    None => (&placeholder[..], ""),
+                };
+                let value = if argument.is_empty() {
+                    next += 1;
+                    lookup_position(positional, next - 1)?
+                } else if let Ok(index) = argument.parse::<usize>() {
+                    lookup_position(positional, index)?
+                } else {
+                    match named.get(argument) {
+                        Some(value) => value,
+                        None => {
+                            return Err(error(&format!(
+                                "format has no argument named '{}'.",
+                                argument
+                            )))
+                        }
+                    }
+                };
+                let spec = Spec::parse(spec)
+                    .ok_or_else(|| error(&format!("Invalid placeholder '{{{}}}'.", placeholder)))?;
+                text.push_str(&spec.apply(value, output)?);
+            }
+            c => text.push(c),
+        }
+    }
+    return Ok(text);
+}
+
+fn error(message: &str) -> RuntimeError {
+    return RuntimeError::new(String::from(message));
+}
+
+fn lookup_position(positional: &[Value], index: usize) -> Result<&Value, RuntimeError> {
+    return positional.get(index).ok_or_else(|| {
+        error(&format!(
+            "format has no argument {}; only {} were given.",
+            index,
+            positional.len()
+        ))
+    });
+}
+
+struct Spec {
+    fill: char,
+    align: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Option<Spec> {
+        let chars: Vec<char> = spec.chars().collect();
+        let is_align = |c: Option<&char>| matches!(c, Some('<') | Some('>') | Some('^'));
+        let mut rest = &chars[..];
+        let mut fill = ' ';
+        let mut align = None;
+        if is_align(rest.get(1)) {
+            fill = rest[0];
+            align = Some(rest[1]);
+            rest = &rest[2..];
+        } else if is_align(rest.first()) {
+            align = Some(rest[0]);
+            rest = &rest[1..];
+        }
+        let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        let width = match digits {
+            0 => 0,
+            _ => rest[..digits].iter().collect::<String>().parse().ok()?,
+        };
+        rest = &rest[digits..];
+        let mut precision = None;
+        if rest.first() == Some(&'.') {
+            let digits = rest[1..].iter().take_while(|c| c.is_ascii_digit()).count();
+            if digits == 0 {
+                return None;
+            }
+            precision = Some(rest[1..=digits].iter().collect::<String>().parse().ok()?);
+            rest = &rest[digits + 1..];
+        }
+        if !rest.is_empty() || width > MAX_WIDTH || precision.unwrap_or(0) > MAX_WIDTH {
+            return None;
+        }
+        return Some(Spec {
+            fill,
+            align,
+            width,
+            precision,
+        });
+    }
+
+    fn apply(&self, value: &Value, output: &mut Output) -> Result<String, RuntimeError> {
+        let mut text = match (value, self.precision) {
+            (Value::Number(number), Some(precision)) => format!("{:.*}", precision, number),
+            (_, Some(precision)) => value.stringify(output)?.chars().take(precision).collect(),
+            (_, None) => value.stringify(output)?,
+        };
+        let length = text.chars().count();
+        if length >= self.width {
+            return Ok(text);
+        }
+        let default = if matches!(value, Value::Number(_)) {
+            '>'
+        } else {
+            '<'
+        };
+        let padding = self.width - length;
+        let (before, after) = match self.align.unwrap_or(default) {
+            '>' => (padding, 0),
+            '^' => (padding / 2, padding - padding / 2),
+            _ => (0, padding),
+        };
+        let fill = |count: usize| self.fill.to_string().repeat(count);
+        text = format!("{}{}{}", fill(before), text, fill(after));
+        return Ok(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(template: &str, positional: &[Value]) -> Result<String, String> {
+        let mut named = BTreeMap::new();
+        named.insert(String::from("name"), Value::String(String::from("Lox")));
+        let mut output = Output::default();
+        return format(template, positional, &named, &mut output).map_err(|error| error.message);
+    }
+
+    fn number(n: f32) -> Value {
+        return Value::Number(n);
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        let arguments = [number(1.0), Value::String(String::from("two"))];
+        assert_eq!(fill("{} and {}", &arguments).unwrap(), "1 and two");
+        assert_eq!(fill("{1} {0} {1}", &arguments).unwrap(), "two 1 two");
+        assert_eq!(fill("hello {name}", &[]).unwrap(), "hello Lox");
+        assert_eq!(fill("{{}} {{{}}}", &arguments).unwrap(), "{} {1}");
+    }
+
+    #[test]
+    fn applies_specs() {
+        let e = [number(1.23456)];
+        assert_eq!(fill("{:.2}", &e).unwrap(), "1.23");
+        assert_eq!(fill("[{:8.3}]", &e).unwrap(), "[   1.235]");
+        assert_eq!(fill("[{:<6}]", &[number(42.0)]).unwrap(), "[42    ]");
+        assert_eq!(fill("[{name:*^7}]", &[]).unwrap(), "[**Lox**]");
+        assert_eq!(fill("[{name:5}]", &[]).unwrap(), "[Lox  ]");
+        assert_eq!(fill("[{name:.2}]", &[]).unwrap(), "[Lo]");
+        assert_eq!(fill("[{:-<2}]", &[number(12345.0)]).unwrap(), "[12345]");
+    }
+
+    #[test]
+    fn reports_malformed_templates() {
+        let one = [number(1.0)];
+        assert_eq!(
+            fill("{", &one).unwrap_err(),
+            "Unclosed '{' in format string."
+        );
+        assert_eq!(
+            fill("}", &one).unwrap_err(),
+            "Unmatched '}' in format string."
+        );
+        assert_eq!(
+            fill("{} {}", &one).unwrap_err(),
+            "format has no argument 1; only 1 were given."
+        );
+        assert_eq!(
+            fill("{other}", &one).unwrap_err(),
+            "format has no argument named 'other'."
+        );
+        assert_eq!(
+            fill("{:.}", &one).unwrap_err(),
+            "Invalid placeholder '{:.}'."
+        );
+        assert_eq!(
+            fill("{:x}", &one).unwrap_err(),
+            "Invalid placeholder '{:x}'."
+        );
+    }
+
+    #[test]
+    fn limits_width_and_precision() {
+        let one = [number(1.0)];
+        assert_eq!(fill("{:1000}", &one).unwrap().len(), 1000);
+        assert_eq!(
+            fill("{:.70000}", &one).unwrap_err(),
+            "Invalid placeholder '{:.70000}'."
+        );
+        assert_eq!(
+            fill("{:>1000000000000}", &one).unwrap_err(),
+            "Invalid placeholder '{:>1000000000000}'."
+        );
+        assert_eq!(
+            fill("{:99999999999999999999999}", &one).unwrap_err(),
+            "Invalid placeholder '{:99999999999999999999999}'."
+        );
+    }
+}
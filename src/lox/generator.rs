@@ -0,0 +1,298 @@
+use crate::lox::{
+    environment::Environment,
+    expr::Expr,
+    interpreter::{RuntimeError, Value},
+    stmt::{Flow, FunctionDecl, Stmt},
+    Output,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A statement that a suspended frame can keep hold of, either an element of
+/// a shared statement list or a shared statement on its own.
+#[derive(Clone)]
+enum StmtRef {
+    InList(Rc<Vec<Stmt>>, usize),
+    Single(Rc<Stmt>),
+}
+
+impl StmtRef {
+    fn get(&self) -> &Stmt {
+        match self {
+            StmtRef::InList(stmts, index) => &stmts[*index],
+            StmtRef::Single(stmt) => stmt,
+        }
+    }
+}
+
+enum Frame {
+    Block {
+        stmts: Rc<Vec<Stmt>>,
+        index: usize,
+        environment: Rc<RefCell<Environment>>,
+    },
+    Single {
+        stmt: Rc<Stmt>,
+        environment: Rc<RefCell<Environment>>,
+    },
+    While {
+        stmt: StmtRef,
+        environment: Rc<RefCell<Environment>>,
+    },
+    ForIn {
+        stmt: StmtRef,
+        iter: Iter,
+        environment: Rc<RefCell<Environment>>,
+    },
+}
+
+enum Next {
+    Pop,
+    Run(StmtRef, Rc<RefCell<Environment>>),
+}
+
+enum Step {
+    Continue,
+    Yield(Value),
+    Return,
+}
+
+/// A suspended call to a generator function. Rather than running the body
+/// on the host stack, the generator keeps its own stack of frames, one per
+/// compound statement it is inside, so it can stop at a `yield` and pick up
+/// from the same place with the same local variables on the next resume.
+pub struct Generator {
+    pub name: String,
+    frames: Vec<Frame>,
+}
+
+impl Generator {
+    pub fn new(declaration: &FunctionDecl, environment: Rc<RefCell<Environment>>) -> Generator {
+        return Generator {
+            name: declaration.name.lexeme.clone(),
+            frames: vec![Frame::Block {
+                stmts: declaration.body.clone(),
+                index: 0,
+                environment,
+            }],
+        };
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.frames.is_empty();
+    }
+
+    pub fn resume_shared(
+        generator: &Rc<RefCell<Generator>>,
+        output: &mut Output,
+    ) -> Result<Option<Value>, RuntimeError> {
+        match generator.try_borrow_mut() {
+            Ok(mut generator) => generator.resume(output),
+            Err(_) => Err(RuntimeError::new(String::from(
+                "Generator is already running.",
+            ))),
+        }
+    }
+
+    /// Runs the body up to the next `yield`, returning the yielded value, or
+    /// `None` once the body has finished. A runtime error finishes the
+    /// generator as well.
+    pub fn resume(&mut self, output: &mut Output) -> Result<Option<Value>, RuntimeError> {
+        output.enter_call()?;
+        let result = self.run(output);
+        output.leave_call();
+        if result.is_err() {
+            self.frames.clear();
+        }
+        return result;
+    }
+
+    fn run(&mut self, output: &mut Output) -> Result<Option<Value>, RuntimeError> {
+        loop {
+            let next = match self.frames.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            let next = match next {
+                Frame::Block {
+                    stmts,
+                    index,
+                    environment,
+                } => {
+                    if *index == stmts.len() {
+                        Next::Pop
+                    } else {
+                        *index += 1;
+                        Next::Run(
+                            StmtRef::InList(stmts.clone(), *index - 1),
+                            environment.clone(),
+                        )
+                    }
+                }
+                Frame::Single { stmt, environment } => {
+                    let next = Next::Run(StmtRef::Single(stmt.clone()), environment.clone());
+                    self.frames.pop();
+                    next
+                }
+                Frame::While { stmt, environment } => match stmt.get() {
+                    Stmt::While(_, condition, body) => {
+                        if Expr::is_truthy(condition.interpret(environment, output)?) {
+                            Next::Run(StmtRef::Single(body.clone()), environment.clone())
+                        } else {
+                            Next::Pop
+                        }
+                    }
+                    _ => unreachable!(),
+                },
+                Frame::ForIn {
+                    stmt,
+                    iter,
+                    environment,
+                } => match (stmt.get(), iter.next(output)?) {
+                    (Stmt::ForIn(_, variable, _, body), Some(value)) => {
+                        let local =
+                            Rc::new(RefCell::new(Environment::with_parent(environment.clone())));
+                        local
+                            .borrow_mut()
+                            .set_variable(variable.lexeme.clone(), value);
+                        Next::Run(StmtRef::Single(body.clone()), local)
+                    }
+                    _ => Next::Pop,
+                },
+            };
+            let (stmt, environment) = match next {
+                Next::Pop => {
+                    self.frames.pop();
+                    continue;
+                }
+                Next::Run(stmt, environment) => (stmt, environment),
+            };
+            match self.execute(stmt, environment, output)? {
+                Step::Continue => {}
+                Step::Yield(value) => return Ok(Some(value)),
+                Step::Return => {
+                    self.frames.clear();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn execute(
+        &mut self,
+        stmt: StmtRef,
+        environment: Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Step, RuntimeError> {
+        match stmt.get() {
+            Stmt::Yield(_, value) => {
+                let value = match value {
+                    Some(expr) => expr.interpret(&environment, output)?,
+                    None => Value::Nil,
+                };
+                return Ok(Step::Yield(value));
+            }
+            Stmt::Block(_, stmts) => self.frames.push(Frame::Block {
+                stmts: stmts.clone(),
+                index: 0,
+                environment: Rc::new(RefCell::new(Environment::with_parent(environment))),
+            }),
+            Stmt::If(_, condition, consequent, alternate) => {
+                let branch = if Expr::is_truthy(condition.interpret(&environment, output)?) {
+                    Some(consequent)
+                } else {
+                    alternate.as_ref()
+                };
+                if let Some(branch) = branch {
+                    self.frames.push(Frame::Single {
+                        stmt: branch.clone(),
+                        environment,
+                    });
+                }
+            }
+            Stmt::While(_, _, _) => self.frames.push(Frame::While {
+                stmt: stmt.clone(),
+                environment,
+            }),
+            Stmt::ForIn(_, variable, iterable, _) => {
+                let iterable = iterable.interpret(&environment, output)?;
+                let iter = Iter::new(iterable).map_err(|error| error.or_at(variable))?;
+                self.frames.push(Frame::ForIn {
+                    stmt: stmt.clone(),
+                    iter,
+                    environment,
+                });
+            }
+            Stmt::Match(_, scrutinee, arms) => {
+                let value = scrutinee.interpret(&environment, output)?;
+                for arm in arms.iter() {
+                    if let Some(local) = arm.pattern.bind(&value, &environment)? {
+                        self.frames.push(Frame::Single {
+                            stmt: arm.body.clone(),
+                            environment: Rc::new(RefCell::new(local)),
+                        });
+                        break;
+                    }
+                }
+            }
+            Stmt::Return(_, _) => {
+                if let Flow::TailCall(call) = stmt.get().interpret(&environment, output)? {
+                    Expr::call(call, output)?;
+                }
+                return Ok(Step::Return);
+            }
+            simple => {
+                simple.interpret(&environment, output)?;
+            }
+        }
+        return Ok(Step::Continue);
+    }
+}
+
+/// The state of a `for (x in ...)` loop over a list, the characters of a
+/// string, the keys of a map or a generator.
+pub enum Iter {
+    List(Rc<RefCell<Vec<Value>>>, usize),
+    /// The string and the byte offset of the next character.
+    String(String, usize),
+    Generator(Rc<RefCell<Generator>>),
+}
+
+impl Iter {
+    pub fn new(iterable: Value) -> Result<Iter, RuntimeError> {
+        let iter = match iterable {
+            Value::List(list) => Iter::List(list, 0),
+            Value::Map(map) => {
+                let keys = map.borrow().keys().cloned().map(Value::String).collect();
+                Iter::List(Rc::new(RefCell::new(keys)), 0)
+            }
+            Value::String(text) => Iter::String(text, 0),
+            Value::Generator(generator) => Iter::Generator(generator),
+            other => {
+                return Err(RuntimeError::new(format!(
+                    "Can only iterate over lists, strings, maps and generators, found {}.",
+                    other
+                )));
+            }
+        };
+        return Ok(iter);
+    }
+
+    pub fn next(&mut self, output: &mut Output) -> Result<Option<Value>, RuntimeError> {
+        match self {
+            Iter::List(list, index) => {
+                let value = list.borrow().get(*index).cloned();
+                *index += 1;
+                Ok(value)
+            }
+            Iter::String(text, offset) => match text[*offset..].chars().next() {
+                Some(next) => {
+                    *offset += next.len_utf8();
+                    Ok(Some(Value::String(next.to_string())))
+                }
+                None => Ok(None),
+            },
+            Iter::Generator(generator) => Generator::resume_shared(generator, output),
+        }
+    }
+}
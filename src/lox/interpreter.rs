@@ -1,7 +1,13 @@
 use crate::lox::environment::Environment;
 use crate::lox::expr::Expr;
-use crate::lox::tokens::{Token, TokenType};
+use crate::lox::generator::Generator;
+use crate::lox::stmt::{ClassDecl, EnumDecl, Flow, FunctionDecl, Stmt};
+use crate::lox::tokens::{Span, Token, TokenType};
+use crate::lox::Output;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
 #[derive(Clone)]
@@ -9,151 +15,1539 @@ pub enum Value {
     Number(f32),
     String(String),
     Boolean(bool),
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+    List(Rc<RefCell<Vec<Value>>>),
+    /// A table from string keys to values, kept in key order.
+    Map(Rc<RefCell<BTreeMap<String, Value>>>),
+    Generator(Rc<RefCell<Generator>>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    Enum(Rc<EnumDecl>),
+    VariantConstructor(Rc<EnumDecl>, usize),
+    Variant(Rc<Variant>),
+    Module(Rc<Module>),
     Nil,
 }
 
-impl fmt::Display for Value {
+pub struct Function {
+    pub declaration: Rc<FunctionDecl>,
+    pub closure: Rc<RefCell<Environment>>,
+}
+
+/// A call whose callee and arguments have been evaluated but which has not
+/// been made yet.
+pub struct Call {
+    pub callee: Value,
+    pub arguments: Vec<Value>,
+    pub named: Vec<(String, Value)>,
+}
+
+/// An error raised while running a program, such as an operator applied to
+/// the wrong kind of value or a native function refusing an operation. It
+/// unwinds to `lox::run`, which reports it.
+pub struct RuntimeError {
+    /// Where the error happened, such as the operator of a failed binary
+    /// expression. Errors from natives, which have no token of their own,
+    /// are placed at the call that raised them.
+    pub span: Option<Span>,
+    pub message: String,
+    /// A suggestion for fixing the error, such as a likely misspelt name.
+    pub help: Option<String>,
+    /// The calls the error has unwound out of, innermost first.
+    pub trace: Vec<Frame>,
+}
+
+/// A call to a Lox function that a runtime error unwound out of.
+pub struct Frame {
+    pub function: String,
+    /// Where the function was called from, once the error has unwound as far
+    /// as the call.
+    pub call_site: Option<Span>,
+}
+
+impl RuntimeError {
+    pub fn new(message: String) -> RuntimeError {
+        return RuntimeError {
+            span: None,
+            message,
+            help: None,
+            trace: vec![],
+        };
+    }
+
+    pub fn at(token: &Token, message: String) -> RuntimeError {
+        return RuntimeError {
+            span: Some(token.span()),
+            ..RuntimeError::new(message)
+        };
+    }
+
+    /// Records that the error unwound out of the body of `function`.
+    pub fn leaving(mut self, function: &Token) -> RuntimeError {
+        self.trace.push(Frame {
+            function: function.lexeme.clone(),
+            call_site: None,
+        });
+        return self;
+    }
+
+    /// Records `paren` as where the function the error last left was called,
+    /// unless that is already known. Calls made by natives, such as the
+    /// callback `map` makes, are placed at the call to the native.
+    pub fn called_at(mut self, paren: &Token) -> RuntimeError {
+        if let Some(frame) = self.trace.last_mut() {
+            if frame.call_site.is_none() {
+                frame.call_site = Some(paren.span());
+            }
+        }
+        return self;
+    }
+
+    /// The trace as lines such as `in fib at line 3` and `called from main
+    /// at line 10`, innermost first. A line repeated by recursion is shown
+    /// once with a count, and lines after the first `limit` are summarised.
+    /// A function that made a tail call has already returned, so it is not
+    /// in the trace.
+    pub fn backtrace(&self, limit: usize) -> Vec<String> {
+        if self.trace.is_empty() {
+            return vec![];
+        }
+        let mut lines = vec![match self.span {
+            Some(span) => format!("in {} at line {}", self.trace[0].function, span.line),
+            None => format!("in {}", self.trace[0].function),
+        }];
+        for (i, frame) in self.trace.iter().enumerate() {
+            let caller = match self.trace.get(i + 1) {
+                Some(caller) => &caller.function[..],
+                None => "the top level",
+            };
+            match frame.call_site {
+                Some(span) => lines.push(format!("called from {} at line {}", caller, span.line)),
+                None => lines.push(format!("called from {}", caller)),
+            }
+        }
+
+        let mut collapsed: Vec<(String, usize)> = vec![];
+        for line in lines {
+            match collapsed.last_mut() {
+                Some((last, count)) if *last == line => *count += 1,
+                _ => collapsed.push((line, 1)),
+            }
+        }
+        let hidden = collapsed.len().saturating_sub(limit);
+        let mut backtrace: Vec<String> = collapsed
+            .into_iter()
+            .take(limit)
+            .map(|(line, count)| match count {
+                1 => line,
+                _ => format!("{} ({} times)", line, count),
+            })
+            .collect();
+        if hidden > 0 {
+            backtrace.push(format!("... and {} more", hidden));
+        }
+        return backtrace;
+    }
+
+    /// Places the error at `token` unless it already has a place, so an
+    /// error keeps the innermost location it unwinds through.
+    pub fn or_at(mut self, token: &Token) -> RuntimeError {
+        if self.span.is_none() {
+            self.span = Some(token.span());
+        }
+        return self;
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Function {
+    /// Returns a copy of the method whose environment has `this` bound to
+    /// `instance`.
+    pub fn bind_this(&self, instance: Value) -> Function {
+        let mut environment = Environment::with_parent(self.closure.clone());
+        environment.set_variable(String::from("this"), instance);
+        return Function {
+            declaration: self.declaration.clone(),
+            closure: Rc::new(RefCell::new(environment)),
+        };
+    }
+
+    /// The number of arguments the function needs, leaving out parameters
+    /// with defaults and the rest parameter.
+    pub fn arity(&self) -> usize {
+        let params = self.declaration.params.iter();
+        return params
+            .filter(|param| param.default.is_none() && !param.rest)
+            .count();
+    }
+
+    /// Runs the body once. A call in tail position is handed back as
+    /// `Flow::TailCall` rather than made, so the caller can run it in place.
+    fn invoke(
+        &self,
+        arguments: Vec<Value>,
+        named: Vec<(String, Value)>,
+        output: &mut Output,
+    ) -> Result<Flow, RuntimeError> {
+        let environment = Rc::new(RefCell::new(Environment::with_parent(self.closure.clone())));
+        self.bind(arguments, named, &environment, output)?;
+        if self.declaration.generator {
+            let generator = Generator::new(&self.declaration, environment);
+            return Ok(Flow::Return(Value::Generator(Rc::new(RefCell::new(
+                generator,
+            )))));
+        }
+        return Stmt::interpret_block(&self.declaration.body, &environment, output)
+            .map_err(|error| error.leaving(&self.declaration.name));
+    }
+
+    /// Binds positional and named arguments to the parameters, filling the
+    /// gaps from default expressions and collecting any extra positional
+    /// arguments into the rest parameter. Defaults are evaluated on every call
+    /// in the new environment, so they may refer to earlier parameters.
+    fn bind(
+        &self,
+        arguments: Vec<Value>,
+        named: Vec<(String, Value)>,
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<(), RuntimeError> {
+        let params = &self.declaration.params;
+        let rest = params.last().filter(|param| param.rest);
+        let positional = params.len() - rest.iter().count();
+
+        if arguments.len() > positional && rest.is_none() {
+            return Err(RuntimeError::new(format!(
+                "{} takes at most {} but got {}.",
+                self.declaration.signature(),
+                argument_count(positional),
+                arguments.len()
+            )));
+        }
+
+        let mut slots: Vec<Option<Value>> = vec![None; positional];
+        let mut extra = vec![];
+        for (i, argument) in arguments.into_iter().enumerate() {
+            if i < positional {
+                slots[i] = Some(argument);
+            } else {
+                extra.push(argument);
+            }
+        }
+        for (name, value) in named {
+            match params[..positional]
+                .iter()
+                .position(|param| param.name.lexeme == name)
+            {
+                Some(i) if slots[i].is_some() => {
+                    return Err(RuntimeError::new(format!(
+                        "{} got multiple values for '{}'.",
+                        self.declaration.signature(),
+                        name
+                    )));
+                }
+                Some(i) => slots[i] = Some(value),
+                None => {
+                    return Err(RuntimeError::new(format!(
+                        "{} has no parameter named '{}'.",
+                        self.declaration.signature(),
+                        name
+                    )));
+                }
+            }
+        }
+
+        for (param, slot) in params.iter().zip(slots) {
+            let value = match (slot, &param.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.interpret(environment, output)?,
+                (None, None) => {
+                    return Err(RuntimeError::new(format!(
+                        "{} missing argument '{}'.",
+                        self.declaration.signature(),
+                        param.name.lexeme
+                    )));
+                }
+            };
+            environment
+                .borrow_mut()
+                .set_variable(param.name.lexeme.clone(), value);
+        }
+        if let Some(rest) = rest {
+            environment.borrow_mut().set_variable(
+                rest.name.lexeme.clone(),
+                Value::List(Rc::new(RefCell::new(extra))),
+            );
+        }
+        return Ok(());
+    }
+}
+
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<Rc<Class>>,
+    pub methods: HashMap<String, Rc<Function>>,
+}
+
+impl Class {
+    pub fn new(
+        declaration: &ClassDecl,
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<Class, RuntimeError> {
+        let superclass = match &declaration.superclass {
+            Some(name) => match environment
+                .borrow()
+                .get_variable(name.lexeme.clone())
+                .map_err(|error| error.or_at(name))?
+            {
+                Value::Class(class) => Some(class),
+                _ => {
+                    let message = String::from("Superclass must be a class.");
+                    return Err(RuntimeError::at(name, message));
+                }
+            },
+            None => None,
+        };
+        let mut closure = environment.clone();
+        if let Some(superclass) = &superclass {
+            let mut scope = Environment::with_parent(environment.clone());
+            scope.set_variable(String::from("super"), Value::Class(superclass.clone()));
+            closure = Rc::new(RefCell::new(scope));
+        }
+        let mut methods = HashMap::new();
+        for method in declaration.methods.iter() {
+            let function = Function {
+                declaration: method.clone(),
+                closure: closure.clone(),
+            };
+            methods.insert(method.name.lexeme.clone(), Rc::new(function));
+        }
+        return Ok(Class {
+            name: declaration.name.lexeme.clone(),
+            superclass,
+            methods,
+        });
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+        return self
+            .superclass
+            .as_ref()
+            .and_then(|superclass| superclass.find_method(name));
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<String, Value>,
+}
+
+pub type NativeFn = Box<dyn Fn(Vec<Value>, &mut Output) -> Result<Value, RuntimeError>>;
+
+pub struct Native {
+    pub name: String,
+    /// How many arguments the function accepts, up to `usize::MAX` when it
+    /// takes any number.
+    pub arity: RangeInclusive<usize>,
+    /// Whether the function takes named arguments. If it does they are
+    /// passed after the positional ones as a single map, which is empty when
+    /// there are none.
+    pub named: bool,
+    pub function: NativeFn,
+}
+
+impl Native {
+    fn check_arity(&self, got: usize) -> Result<(), RuntimeError> {
+        if self.arity.contains(&got) {
+            return Ok(());
+        }
+        let (min, max) = (*self.arity.start(), *self.arity.end());
+        let message = if min == max {
+            format!("Expected {} but got {}.", argument_count(min), got)
+        } else if max == usize::MAX {
+            format!("Expected at least {} but got {}.", argument_count(min), got)
+        } else {
+            format!("Expected {} to {} arguments but got {}.", min, max, got)
+        };
+        return Err(RuntimeError::new(message));
+    }
+}
+
+/// `count` arguments, in words, as arity errors say it.
+pub(crate) fn argument_count(count: usize) -> String {
+    if count == 1 {
+        return String::from("1 argument");
+    }
+    return format!("{} arguments", count);
+}
+
+/// A named group of values reached through property access, such as `math`.
+pub struct Module {
+    pub name: String,
+    pub members: HashMap<String, Value>,
+}
+
+pub struct Variant {
+    pub declaration: Rc<EnumDecl>,
+    pub index: usize,
+    pub payload: Vec<Value>,
+}
+
+impl Variant {
+    fn name(&self) -> String {
+        return format!(
+            "{}.{}",
+            self.declaration.name.lexeme, self.declaration.variants[self.index].name.lexeme
+        );
+    }
+}
+
+impl fmt::Display for Variant {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if self.payload.is_empty() {
+            return Ok(());
+        }
+        return self.write_payload(f, &mut vec![]);
+    }
+}
+
+impl Variant {
+    /// Writes `(` and the payload values quoted, as `Value::write_quoted`
+    /// does, then `)`.
+    fn write_payload(&self, f: &mut fmt::Formatter, open: &mut Vec<*const ()>) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, value) in self.payload.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            value.write_quoted(f, open)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Value {
+    /// Converts the value to text the way `print` shows it, calling `__str__`
+    /// on instances that define it, including inside lists and variants.
+    pub fn stringify(&self, output: &mut Output) -> Result<String, RuntimeError> {
+        let text = match self {
+            Value::String(_) => format!("{}", self),
+            _ => self.stringify_element(output, &mut vec![])?,
+        };
+        return Ok(text);
+    }
+
+    /// `stringify` for a value inside a container, where strings are quoted.
+    /// `open` holds the lists and maps being stringified further out, as
+    /// for `write_quoted`.
+    fn stringify_element(
+        &self,
+        output: &mut Output,
+        open: &mut Vec<*const ()>,
+    ) -> Result<String, RuntimeError> {
+        let text = match self {
+            Value::Instance(_) => match Expr::call_protocol(self, "__str__", vec![], output)? {
+                Some(Value::String(text)) => text,
+                Some(other) => {
+                    return Err(RuntimeError::new(format!(
+                        "__str__ must return a string, found {}.",
+                        other
+                    )));
+                }
+                None => format!("{}", self),
+            },
+            Value::List(list) => {
+                if !enter(open, Rc::as_ptr(list) as *const ()) {
+                    return Ok(String::from("[...]"));
+                }
+                let elements = list.borrow().clone();
+                let elements = elements
+                    .iter()
+                    .map(|element| element.stringify_element(output, open))
+                    .collect::<Result<Vec<String>, RuntimeError>>()?;
+                open.pop();
+                format!("[{}]", elements.join(", "))
+            }
+            Value::Map(map) => {
+                if !enter(open, Rc::as_ptr(map) as *const ()) {
+                    return Ok(String::from("{...}"));
+                }
+                let entries = map.borrow().clone();
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        Ok(format!(
+                            "{}: {}",
+                            key,
+                            value.stringify_element(output, open)?
+                        ))
+                    })
+                    .collect::<Result<Vec<String>, RuntimeError>>()?;
+                open.pop();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::Variant(variant) if !variant.payload.is_empty() => {
+                let payload = variant
+                    .payload
+                    .iter()
+                    .map(|value| value.stringify_element(output, open))
+                    .collect::<Result<Vec<String>, RuntimeError>>()?;
+                format!("{}({})", variant.name(), payload.join(", "))
+            }
+            _ => format!("{:?}", self),
+        };
+        return Ok(text);
+    }
+
+    /// Writes the value as `Debug` shows it. `open` holds the lists and maps
+    /// being written further out; one met again, or one nested too deeply,
+    /// is written as `[...]` or `{...}` rather than recursing forever.
+    fn write_quoted(&self, f: &mut fmt::Formatter, open: &mut Vec<*const ()>) -> fmt::Result {
         match self {
-            Value::Number(value) => write!(f, "{}", value),
-            Value::String(value) => write!(f, "{}", value),
-            Value::Boolean(value) => write!(f, "{}", value),
-            Value::Nil => write!(f, "Nil"),
+            Value::String(value) => write!(f, "{:?}", value),
+            Value::List(list) => {
+                if !enter(open, Rc::as_ptr(list) as *const ()) {
+                    return write!(f, "[...]");
+                }
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write_quoted(f, open)?;
+                }
+                open.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                if !enter(open, Rc::as_ptr(map) as *const ()) {
+                    return write!(f, "{{...}}");
+                }
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    value.write_quoted(f, open)?;
+                }
+                open.pop();
+                write!(f, "}}")
+            }
+            Value::Variant(variant) if !variant.payload.is_empty() => {
+                write!(f, "{}", variant.name())?;
+                variant.write_payload(f, open)
+            }
+            other => write!(f, "{}", other),
         }
     }
 }
 
-impl fmt::Debug for Value {
+/// How deeply lists, maps and variants may nest before printing or
+/// comparing them stops short. A list that contains itself nests forever.
+const MAX_NESTING: usize = 512;
+
+/// Adds the list or map at `pointer` to those being visited in `open`,
+/// returning false instead when it is already among them or there are
+/// `MAX_NESTING` of them.
+fn enter(open: &mut Vec<*const ()>, pointer: *const ()) -> bool {
+    if open.len() == MAX_NESTING || open.contains(&pointer) {
+        return false;
+    }
+    open.push(pointer);
+    return true;
+}
+
+impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Number(value) => write!(f, "{}", value),
             Value::String(value) => write!(f, "{}", value),
             Value::Boolean(value) => write!(f, "{}", value),
-            Value::Nil => write!(f, "Nil"),
+            Value::Function(function) => write!(f, "<fn {}>", function.declaration.name.lexeme),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Generator(generator) => write!(f, "<generator {}>", generator.borrow().name),
+            Value::Class(class) => write!(f, "<class {}>", class.name),
+            Value::Instance(instance) => write!(f, "<{} instance>", instance.borrow().class.name),
+            Value::List(_) | Value::Map(_) => self.write_quoted(f, &mut vec![]),
+            Value::Enum(declaration) => write!(f, "<enum {}>", declaration.name.lexeme),
+            Value::VariantConstructor(declaration, index) => write!(
+                f,
+                "<variant {}.{}>",
+                declaration.name.lexeme, declaration.variants[*index].name.lexeme
+            ),
+            Value::Variant(variant) => write!(f, "{}", variant),
+            Value::Nil => write!(f, "nil"),
         }
     }
 }
 
+/// Like `Display`, except that strings are quoted so that they can be told
+/// apart from other values. Elements of lists, maps and variants are always
+/// written this way.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return self.write_quoted(f, &mut vec![]);
+    }
+}
+
 impl Expr {
-    pub fn interpret(&self, environment: &mut Box<Environment>) -> Value {
+    /// Evaluates the expression. An error from inside it that has no place
+    /// yet is placed at this expression's token.
+    pub fn interpret(
+        &self,
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let result = self.evaluate(environment, output);
+        return match self.token() {
+            Some(token) => result.map_err(|error| error.or_at(token)),
+            None => result,
+        };
+    }
+
+    /// The token errors in this expression are reported at: its operator,
+    /// name, bracket or the `(` of a call.
+    fn token(&self) -> Option<&Token> {
         match self {
+            Expr::Binary { op, .. } | Expr::Unary { op, .. } => Some(op),
+            Expr::Grouping(_, _) => None,
+            Expr::Literal(token) | Expr::Var(token) | Expr::This(token) => Some(token),
+            Expr::Assignment(name, _)
+            | Expr::Get { name, .. }
+            | Expr::Set { name, .. }
+            | Expr::Super { method: name, .. } => Some(name),
+            Expr::Call { paren, .. } => Some(paren),
+            Expr::List { bracket, .. }
+            | Expr::Index { bracket, .. }
+            | Expr::SetIndex { bracket, .. } => Some(bracket),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let value = match self {
             Expr::Binary { left, right, op } => {
-                Expr::interpret_binary(left, right, op, environment)
+                Expr::interpret_binary(left, right, op, environment, output)?
+            }
+            Expr::Unary { right, op } => Expr::interpret_unary(right, op, environment, output)?,
+            Expr::Grouping(_, expr) => expr.interpret(environment, output)?,
+            Expr::Literal(token) => Expr::interpret_literal(token)?,
+            Expr::Var(token) => environment.borrow().get_variable(token.lexeme.clone())?,
+            Expr::Assignment(token, right) => {
+                let result = right.interpret(environment, output)?;
+                environment
+                    .borrow_mut()
+                    .assign_variable(token.lexeme.clone(), result.clone())?;
+                result
             }
-            Expr::Unary { right, op } => Expr::interpret_unary(right, op, environment),
-            Expr::Grouping(expr) => expr.interpret(environment),
-            Expr::Literal(token) => Expr::interpret_literal(token, environment),
-            Expr::Var(token) => {
-                println!("Var Expression");
-                if let TokenType::Identifier(identifier) = &token.token_type {
-                    return environment.get_variable(identifier.to_string()).clone();
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                named,
+            } => {
+                let call = Expr::evaluate_call(callee, arguments, named, environment, output)?;
+                Expr::call(call, output).map_err(|error| error.called_at(paren))?
+            }
+            Expr::Get { object, name } => {
+                let object = object.interpret(environment, output)?;
+                Expr::get_property(object, name)?
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object = object.interpret(environment, output)?;
+                let value = value.interpret(environment, output)?;
+                match object {
+                    Value::Instance(instance) => {
+                        instance
+                            .borrow_mut()
+                            .fields
+                            .insert(name.lexeme.clone(), value.clone());
+                        value
+                    }
+                    other => {
+                        return Err(RuntimeError::new(format!(
+                            "Only instances have fields, found {}.",
+                            other
+                        )));
+                    }
                 }
-                panic!("Expected Identifier")
             }
-            Expr::Assignment(token, right) => {
-                if let TokenType::Identifier(id) = token.token_type.clone() {
-                    let result = right.interpret(environment);
-                    environment.assign_variable(id, result.clone());
-                    return result;
+            Expr::This(_) => environment.borrow().get_variable(String::from("this"))?,
+            Expr::Super { method, .. } => {
+                let superclass = environment.borrow().get_variable(String::from("super"))?;
+                let instance = environment.borrow().get_variable(String::from("this"))?;
+                match superclass {
+                    Value::Class(superclass) => match superclass.find_method(&method.lexeme) {
+                        Some(found) => Value::Function(Rc::new(found.bind_this(instance))),
+                        None => {
+                            return Err(RuntimeError::new(format!(
+                                "Undefined property '{}'.",
+                                method.lexeme
+                            )));
+                        }
+                    },
+                    _ => {
+                        return Err(RuntimeError::new(String::from(
+                            "Can't use 'super' in a class with no superclass.",
+                        )));
+                    }
                 }
-                panic!("Expected Identifier")
             }
-            _ => panic!("Unsupported Expr"),
+            Expr::List { elements, .. } => {
+                let mut values = vec![];
+                for element in elements.iter() {
+                    values.push(element.interpret(environment, output)?);
+                }
+                Value::List(Rc::new(RefCell::new(values)))
+            }
+            Expr::Index { object, index, .. } => {
+                let object = object.interpret(environment, output)?;
+                let index = index.interpret(environment, output)?;
+                match object {
+                    Value::List(list) => {
+                        let list = list.borrow();
+                        list[Expr::list_index(list.len(), index)?].clone()
+                    }
+                    Value::Map(map) => {
+                        let key = Expr::map_key(index)?;
+                        map.borrow().get(&key).cloned().unwrap_or(Value::Nil)
+                    }
+                    Value::String(text) => {
+                        // Strings are indexed by character, not by byte.
+                        let index = Expr::list_index(text.chars().count(), index)?;
+                        Value::String(text.chars().nth(index).unwrap().to_string())
+                    }
+                    Value::Instance(_) => {
+                        match Expr::call_protocol(&object, "__index__", vec![index], output)? {
+                            Some(value) => value,
+                            None => {
+                                return Err(RuntimeError::new(format!(
+                                    "{} has no __index__ method.",
+                                    object
+                                )));
+                            }
+                        }
+                    }
+                    other => return Err(Expr::not_indexable(&other)),
+                }
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                let object = object.interpret(environment, output)?;
+                let index = index.interpret(environment, output)?;
+                let value = value.interpret(environment, output)?;
+                match object {
+                    Value::List(list) => {
+                        let mut list = list.borrow_mut();
+                        let index = Expr::list_index(list.len(), index)?;
+                        list[index] = value.clone();
+                        value
+                    }
+                    Value::Instance(_) => {
+                        let arguments = vec![index, value.clone()];
+                        match Expr::call_protocol(&object, "__setindex__", arguments, output)? {
+                            Some(_) => value,
+                            None => {
+                                return Err(RuntimeError::new(format!(
+                                    "{} has no __setindex__ method.",
+                                    object
+                                )));
+                            }
+                        }
+                    }
+                    Value::Map(map) => {
+                        map.borrow_mut()
+                            .insert(Expr::map_key(index)?, value.clone());
+                        value
+                    }
+                    Value::String(_) => {
+                        return Err(RuntimeError::new(String::from("Strings are immutable.")));
+                    }
+                    other => return Err(Expr::not_indexable(&other)),
+                }
+            }
+        };
+        return Ok(value);
+    }
+
+    pub fn evaluate_call(
+        callee: &Expr,
+        arguments: &[Expr],
+        named: &[(Token, Expr)],
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Call, RuntimeError> {
+        let callee = callee.interpret(environment, output)?;
+        let mut values = vec![];
+        for argument in arguments.iter() {
+            values.push(argument.interpret(environment, output)?);
+        }
+        let mut named_values = vec![];
+        for (name, argument) in named.iter() {
+            named_values.push((
+                name.lexeme.clone(),
+                argument.interpret(environment, output)?,
+            ));
         }
+        return Ok(Call {
+            callee,
+            arguments: values,
+            named: named_values,
+        });
     }
 
-    fn interpret_unary(right: &Expr, op: &Token, environment: &mut Box<Environment>) -> Value {
-        let right_val = right.interpret(environment);
+    /// Calls `callee`, following tail calls in a loop so that tail recursion
+    /// runs in constant host stack space.
+    pub fn call(mut call: Call, output: &mut Output) -> Result<Value, RuntimeError> {
+        loop {
+            let function = match call.callee {
+                Value::Function(function) => function,
+                Value::Class(class) => {
+                    return Expr::instantiate(class, call.arguments, call.named, output)
+                }
+                callee => return Expr::call_builtin(callee, call.arguments, call.named, output),
+            };
+            output.enter_call()?;
+            let flow = function.invoke(call.arguments, call.named, output);
+            output.leave_call();
+            match flow? {
+                Flow::TailCall(next) => call = next,
+                Flow::Return(value) => return Ok(value),
+                Flow::Normal => return Ok(Value::Nil),
+            }
+        }
+    }
 
-        match op.token_type {
-            TokenType::Minus => Value::Number(-1.0 * Expr::get_number(right_val)),
-            TokenType::Bang => Value::Boolean(!Expr::is_truthy(right_val)),
-            _ => panic!("Error: Expected Unary Operation"),
+    fn instantiate(
+        class: Rc<Class>,
+        arguments: Vec<Value>,
+        named: Vec<(String, Value)>,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Value::Instance(Rc::new(RefCell::new(Instance {
+            class: class.clone(),
+            fields: HashMap::new(),
+        })));
+        match class.find_method("init") {
+            Some(init) => {
+                let call = Call {
+                    callee: Value::Function(Rc::new(init.bind_this(instance.clone()))),
+                    arguments,
+                    named,
+                };
+                Expr::call(call, output)?;
+            }
+            None if !arguments.is_empty() || !named.is_empty() => {
+                return Err(RuntimeError::new(format!(
+                    "Expected 0 arguments but got {}.",
+                    arguments.len() + named.len()
+                )));
+            }
+            None => {}
         }
+        return Ok(instance);
     }
 
-    fn interpret_literal(token: &Token, environment: &mut Box<Environment>) -> Value {
-        match &token.token_type {
+    /// Calls the special method `name` on `receiver`, or returns `None` when
+    /// the receiver is not an instance of a class defining it.
+    pub fn call_protocol(
+        receiver: &Value,
+        name: &str,
+        arguments: Vec<Value>,
+        output: &mut Output,
+    ) -> Result<Option<Value>, RuntimeError> {
+        if let Value::Instance(instance) = receiver {
+            let method = match instance.borrow().class.find_method(name) {
+                Some(method) => method,
+                None => return Ok(None),
+            };
+            let call = Call {
+                callee: Value::Function(Rc::new(method.bind_this(receiver.clone()))),
+                arguments,
+                named: vec![],
+            };
+            return Expr::call(call, output).map(Some);
+        }
+        return Ok(None);
+    }
+
+    fn call_builtin(
+        callee: Value,
+        mut arguments: Vec<Value>,
+        named: Vec<(String, Value)>,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let takes_named = matches!(&callee, Value::Native(native) if native.named);
+        if !named.is_empty() && !takes_named {
+            return Err(RuntimeError::new(format!(
+                "{} does not take named arguments.",
+                callee
+            )));
+        }
+        match callee {
+            Value::Native(native) => {
+                native.check_arity(arguments.len())?;
+                if native.named {
+                    let named = named.into_iter().collect();
+                    arguments.push(Value::Map(Rc::new(RefCell::new(named))));
+                }
+                (native.function)(arguments, output)
+            }
+            Value::VariantConstructor(declaration, index) => {
+                let fields = declaration.variants[index].fields.len();
+                if arguments.len() != fields {
+                    return Err(RuntimeError::new(format!(
+                        "Expected {} but got {}.",
+                        argument_count(fields),
+                        arguments.len()
+                    )));
+                }
+                Ok(Value::Variant(Rc::new(Variant {
+                    declaration,
+                    index,
+                    payload: arguments,
+                })))
+            }
+            other => Err(RuntimeError::new(format!(
+                "Can only call functions and classes, found {}.",
+                other
+            ))),
+        }
+    }
+
+    fn generator_method(
+        generator: Rc<RefCell<Generator>>,
+        name: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let function: NativeFn = match &name.lexeme[..] {
+            "next" => Box::new(move |_, output| {
+                Ok(Generator::resume_shared(&generator, output)?.unwrap_or(Value::Nil))
+            }),
+            "done" => Box::new(move |_, _| Ok(Value::Boolean(generator.borrow().is_done()))),
+            _ => return Err(Expr::no_method("generator", name)),
+        };
+        return Ok(Value::Native(Rc::new(Native {
+            name: name.lexeme.clone(),
+            arity: 0..=0,
+            named: false,
+            function,
+        })));
+    }
+
+    fn list_index(len: usize, index: Value) -> Result<usize, RuntimeError> {
+        let index = match index {
+            Value::Number(index) => index,
+            other => {
+                return Err(RuntimeError::new(format!(
+                    "Index must be a number, found {}.",
+                    other
+                )));
+            }
+        };
+        if index < 0.0 || index.fract() != 0.0 || index as usize >= len {
+            return Err(RuntimeError::new(format!("Index {} out of range.", index)));
+        }
+        return Ok(index as usize);
+    }
+
+    fn not_indexable(object: &Value) -> RuntimeError {
+        return RuntimeError::new(format!(
+            "Only lists, strings, maps and instances can be indexed, found {}.",
+            object
+        ));
+    }
+
+    fn no_method(kind: &str, name: &Token) -> RuntimeError {
+        let message = format!("A {} has no method '{}'.", kind, name.lexeme);
+        return RuntimeError::at(name, message);
+    }
+
+    fn list_method(list: Rc<RefCell<Vec<Value>>>, name: &Token) -> Result<Value, RuntimeError> {
+        let (arity, function): (usize, NativeFn) = match &name.lexeme[..] {
+            "len" => (
+                0,
+                Box::new(move |_, _| Ok(Value::Number(list.borrow().len() as f32))),
+            ),
+            "push" => (
+                1,
+                Box::new(move |arguments, _| {
+                    list.borrow_mut().extend(arguments);
+                    Ok(Value::Nil)
+                }),
+            ),
+            "pop" => (
+                0,
+                Box::new(move |_, _| Ok(list.borrow_mut().pop().unwrap_or(Value::Nil))),
+            ),
+            _ => return Err(Expr::no_method("list", name)),
+        };
+        return Ok(Value::Native(Rc::new(Native {
+            name: name.lexeme.clone(),
+            arity: arity..=arity,
+            named: false,
+            function,
+        })));
+    }
+
+    fn map_key(key: Value) -> Result<String, RuntimeError> {
+        match key {
+            Value::String(key) => Ok(key),
+            other => Err(RuntimeError::new(format!(
+                "Map keys must be strings, found {}.",
+                other
+            ))),
+        }
+    }
+
+    fn map_method(
+        map: Rc<RefCell<BTreeMap<String, Value>>>,
+        name: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let (arity, function): (usize, NativeFn) = match &name.lexeme[..] {
+            "len" => (
+                0,
+                Box::new(move |_, _| Ok(Value::Number(map.borrow().len() as f32))),
+            ),
+            "keys" => (
+                0,
+                Box::new(move |_, _| {
+                    let keys = map.borrow().keys().cloned().map(Value::String).collect();
+                    Ok(Value::List(Rc::new(RefCell::new(keys))))
+                }),
+            ),
+            "values" => (
+                0,
+                Box::new(move |_, _| {
+                    let values = map.borrow().values().cloned().collect();
+                    Ok(Value::List(Rc::new(RefCell::new(values))))
+                }),
+            ),
+            "has" => (
+                1,
+                Box::new(move |arguments, _| {
+                    let key = Expr::map_key(arguments[0].clone())?;
+                    Ok(Value::Boolean(map.borrow().contains_key(&key)))
+                }),
+            ),
+            "remove" => (
+                1,
+                Box::new(move |arguments, _| {
+                    let key = Expr::map_key(arguments[0].clone())?;
+                    Ok(map.borrow_mut().remove(&key).unwrap_or(Value::Nil))
+                }),
+            ),
+            _ => return Err(Expr::no_method("map", name)),
+        };
+        return Ok(Value::Native(Rc::new(Native {
+            name: name.lexeme.clone(),
+            arity: arity..=arity,
+            named: false,
+            function,
+        })));
+    }
+
+    /// Methods on strings. Positions and lengths count characters rather than
+    /// bytes, so they agree with indexing.
+    fn string_method(text: String, name: &Token) -> Result<Value, RuntimeError> {
+        let (arity, function): (usize, NativeFn) = match &name.lexeme[..] {
+            "len" => (
+                0,
+                Box::new(move |_, _| Ok(Value::Number(text.chars().count() as f32))),
+            ),
+            "upper" => (
+                0,
+                Box::new(move |_, _| Ok(Value::String(text.to_uppercase()))),
+            ),
+            "lower" => (
+                0,
+                Box::new(move |_, _| Ok(Value::String(text.to_lowercase()))),
+            ),
+            "trim" => (
+                0,
+                Box::new(move |_, _| Ok(Value::String(String::from(text.trim())))),
+            ),
+            "find" => (
+                1,
+                Box::new(move |arguments, _| {
+                    let pattern = Expr::get_string("find", &arguments[0])?;
+                    Ok(match text.find(&pattern) {
+                        Some(byte) => Value::Number(text[..byte].chars().count() as f32),
+                        None => Value::Number(-1.0),
+                    })
+                }),
+            ),
+            "split" => (
+                1,
+                Box::new(move |arguments, _| {
+                    let separator = Expr::get_string("split", &arguments[0])?;
+                    let parts: Vec<Value> = if separator.is_empty() {
+                        text.chars().map(|c| Value::String(c.to_string())).collect()
+                    } else {
+                        text.split(&separator[..])
+                            .map(|part| Value::String(String::from(part)))
+                            .collect()
+                    };
+                    Ok(Value::List(Rc::new(RefCell::new(parts))))
+                }),
+            ),
+            "replace" => (
+                2,
+                Box::new(move |arguments, _| {
+                    let from = Expr::get_string("replace", &arguments[0])?;
+                    let to = Expr::get_string("replace", &arguments[1])?;
+                    if from.is_empty() {
+                        return Err(RuntimeError::new(String::from(
+                            "Cannot replace an empty string.",
+                        )));
+                    }
+                    Ok(Value::String(text.replace(&from[..], &to)))
+                }),
+            ),
+            "starts_with" => (
+                1,
+                Box::new(move |arguments, _| {
+                    let prefix = Expr::get_string("starts_with", &arguments[0])?;
+                    Ok(Value::Boolean(text.starts_with(&prefix[..])))
+                }),
+            ),
+            "ends_with" => (
+                1,
+                Box::new(move |arguments, _| {
+                    let suffix = Expr::get_string("ends_with", &arguments[0])?;
+                    Ok(Value::Boolean(text.ends_with(&suffix[..])))
+                }),
+            ),
+            "substring" => (
+                2,
+                Box::new(move |arguments, _| {
+                    let len = text.chars().count();
+                    let start = Expr::get_number("substring", &arguments[0])?;
+                    let end = Expr::get_number("substring", &arguments[1])?;
+                    if start < 0.0
+                        || start > end
+                        || end > len as f32
+                        || start.fract() != 0.0
+                        || end.fract() != 0.0
+                    {
+                        return Err(RuntimeError::new(format!(
+                            "Substring {}..{} out of range.",
+                            start, end
+                        )));
+                    }
+                    let taken = (end - start) as usize;
+                    let substring = text.chars().skip(start as usize).take(taken).collect();
+                    Ok(Value::String(substring))
+                }),
+            ),
+            _ => return Err(Expr::no_method("string", name)),
+        };
+        return Ok(Value::Native(Rc::new(Native {
+            name: name.lexeme.clone(),
+            arity: arity..=arity,
+            named: false,
+            function,
+        })));
+    }
+
+    fn get_property(object: Value, name: &Token) -> Result<Value, RuntimeError> {
+        let undefined = |message: String| Err(RuntimeError::at(name, message));
+        let value = match object {
+            Value::Enum(declaration) => match declaration.variant(&name.lexeme) {
+                Some(index) if declaration.variants[index].fields.is_empty() => {
+                    Value::Variant(Rc::new(Variant {
+                        declaration,
+                        index,
+                        payload: vec![],
+                    }))
+                }
+                Some(index) => Value::VariantConstructor(declaration, index),
+                None => {
+                    return undefined(format!(
+                        "Enum {} has no variant '{}'.",
+                        declaration.name.lexeme, name.lexeme
+                    ));
+                }
+            },
+            Value::Variant(variant) => {
+                let fields = &variant.declaration.variants[variant.index].fields;
+                match fields.iter().position(|field| field.lexeme == name.lexeme) {
+                    Some(field) => variant.payload[field].clone(),
+                    None => {
+                        return undefined(format!(
+                            "{} has no field '{}'.",
+                            variant.name(),
+                            name.lexeme
+                        ));
+                    }
+                }
+            }
+            Value::List(list) => Expr::list_method(list, name)?,
+            Value::Map(map) => Expr::map_method(map, name)?,
+            Value::String(text) => Expr::string_method(text, name)?,
+            Value::Module(module) => match module.members.get(&name.lexeme) {
+                Some(member) => member.clone(),
+                None => {
+                    return undefined(format!(
+                        "Module {} has no member '{}'.",
+                        module.name, name.lexeme
+                    ));
+                }
+            },
+            Value::Generator(generator) => Expr::generator_method(generator, name)?,
+            Value::Instance(instance) => {
+                let field = instance.borrow().fields.get(&name.lexeme).cloned();
+                if let Some(field) = field {
+                    return Ok(field);
+                }
+                let method = instance.borrow().class.find_method(&name.lexeme);
+                match method {
+                    Some(method) => {
+                        Value::Function(Rc::new(method.bind_this(Value::Instance(instance))))
+                    }
+                    None => return undefined(format!("Undefined property '{}'.", name.lexeme)),
+                }
+            }
+            Value::Function(function) => match &name.lexeme[..] {
+                "name" => Value::String(function.declaration.name.lexeme.clone()),
+                "arity" => Value::Number(function.arity() as f32),
+                "params" => {
+                    let params = function.declaration.params.iter();
+                    let names = params
+                        .map(|param| Value::String(param.name.lexeme.clone()))
+                        .collect();
+                    Value::List(Rc::new(RefCell::new(names)))
+                }
+                _ => {
+                    return undefined(format!("Functions have no property '{}'.", name.lexeme));
+                }
+            },
+            Value::Native(native) => match &name.lexeme[..] {
+                "name" => Value::String(native.name.clone()),
+                "arity" => Value::Number(*native.arity.start() as f32),
+                _ => {
+                    return undefined(format!("Functions have no property '{}'.", name.lexeme));
+                }
+            },
+            other => {
+                return undefined(format!(
+                    "Only instances, enums, variants and functions have properties, found {}.",
+                    other
+                ));
+            }
+        };
+        return Ok(value);
+    }
+
+    fn interpret_unary(
+        right: &Expr,
+        op: &Token,
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let right_val = right.interpret(environment, output)?;
+
+        let value = match op.token_type {
+            TokenType::Minus if matches!(right_val, Value::Instance(_)) => {
+                match Expr::call_protocol(&right_val, "__neg__", vec![], output)? {
+                    Some(value) => value,
+                    None => {
+                        let message = format!("{} has no __neg__ method.", right_val);
+                        return Err(RuntimeError::at(op, message));
+                    }
+                }
+            }
+            TokenType::Minus => match right_val {
+                Value::Number(number) => Value::Number(-number),
+                _ => {
+                    let message = String::from("Operand must be a number.");
+                    return Err(RuntimeError::at(op, message));
+                }
+            },
+            TokenType::Bang => Value::Boolean(!Expr::is_truthy(right_val)),
+            _ => unreachable!("the parser only builds unary '-' and '!'"),
+        };
+        return Ok(value);
+    }
+
+    /// The value of a literal. The parser stands an error token in as a
+    /// literal so it can carry on past a lexical error; programs with one
+    /// are not run, but one that is anyway fails here rather than running
+    /// on with a made up value.
+    fn interpret_literal(token: &Token) -> Result<Value, RuntimeError> {
+        let value = match &token.token_type {
             TokenType::Number(value) => Value::Number(*value),
             TokenType::String(value) => Value::String(value.clone()),
             TokenType::True => Value::Boolean(true),
             TokenType::False => Value::Boolean(false),
             TokenType::Nil => Value::Nil,
-            _ => panic!("Error: Expected Literal"),
-        }
+            _ => {
+                let message = format!("Cannot run '{}', which did not scan.", token.lexeme);
+                return Err(RuntimeError::at(token, message));
+            }
+        };
+        return Ok(value);
     }
 
     fn interpret_binary(
         left: &Expr,
         right: &Expr,
         op: &Token,
-        environment: &mut Box<Environment>,
-    ) -> Value {
-        let left_val = left.interpret(environment);
-        let right_val = right.interpret(environment);
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let left_val = left.interpret(environment, output)?;
+        let right_val = right.interpret(environment, output)?;
 
-        match op.token_type {
+        if matches!(left_val, Value::Instance(_)) || matches!(right_val, Value::Instance(_)) {
+            return Expr::interpret_overloaded(op, &left_val, &right_val, output);
+        }
+
+        let numbers = || match (&left_val, &right_val) {
+            (Value::Number(left), Value::Number(right)) => Ok((*left, *right)),
+            _ => {
+                let message = String::from("Operands must be numbers.");
+                Err(RuntimeError::at(op, message))
+            }
+        };
+        let value = match op.token_type {
             //Arithmetic operations
-            TokenType::Plus => Expr::interpret_plus(left_val, right_val),
+            TokenType::Plus => Expr::interpret_plus(op, left_val, right_val)?,
             TokenType::Star => {
-                Value::Number(Expr::get_number(left_val) * Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Number(left * right)
             }
             TokenType::Minus => {
-                Value::Number(Expr::get_number(left_val) - Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Number(left - right)
             }
             TokenType::Slash => {
-                Value::Number(Expr::get_number(left_val) / Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Number(left / right)
             }
             //Comparisons
             TokenType::Less => {
-                Value::Boolean(Expr::get_number(left_val) < Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Boolean(left < right)
             }
             TokenType::LessEqual => {
-                Value::Boolean(Expr::get_number(left_val) <= Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Boolean(left <= right)
             }
             TokenType::Greater => {
-                Value::Boolean(Expr::get_number(left_val) > Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Boolean(left > right)
             }
             TokenType::GreaterEqual => {
-                Value::Boolean(Expr::get_number(left_val) >= Expr::get_number(right_val))
+                let (left, right) = numbers()?;
+                Value::Boolean(left >= right)
             }
             //Equality
-            TokenType::EqualEqual => Value::Boolean(Expr::is_equal(left_val, right_val)),
-            TokenType::BangEqual => Value::Boolean(!Expr::is_equal(left_val, right_val)),
-            _ => panic!("Error: Expected Binary Operation"),
+            TokenType::EqualEqual => {
+                Value::Boolean(Expr::is_equal(&left_val, &right_val).map_err(|e| e.or_at(op))?)
+            }
+            TokenType::BangEqual => {
+                Value::Boolean(!Expr::is_equal(&left_val, &right_val).map_err(|e| e.or_at(op))?)
+            }
+            _ => unreachable!("the parser only builds binary expressions from operators"),
+        };
+        return Ok(value);
+    }
+
+    /// Dispatches a binary operator with an instance operand to the special
+    /// method for it, trying the reflected method on the right operand when
+    /// the left one does not define it.
+    fn interpret_overloaded(
+        op: &Token,
+        left: &Value,
+        right: &Value,
+        output: &mut Output,
+    ) -> Result<Value, RuntimeError> {
+        let (method, reflected) = match op.token_type {
+            TokenType::Plus => ("__add__", "__radd__"),
+            TokenType::Minus => ("__sub__", "__rsub__"),
+            TokenType::Star => ("__mul__", "__rmul__"),
+            TokenType::Slash => ("__div__", "__rdiv__"),
+            TokenType::Less => ("__lt__", "__gt__"),
+            TokenType::Greater => ("__gt__", "__lt__"),
+            TokenType::LessEqual => ("__le__", "__ge__"),
+            TokenType::GreaterEqual => ("__ge__", "__le__"),
+            TokenType::EqualEqual => {
+                return Ok(Value::Boolean(Expr::instances_equal(left, right, output)?));
+            }
+            TokenType::BangEqual => {
+                return Ok(Value::Boolean(!Expr::instances_equal(left, right, output)?));
+            }
+            _ => unreachable!("the parser only builds binary expressions from operators"),
+        };
+        if let Some(result) = Expr::call_protocol(left, method, vec![right.clone()], output)? {
+            return Ok(result);
+        }
+        if let Some(result) = Expr::call_protocol(right, reflected, vec![left.clone()], output)? {
+            return Ok(result);
         }
+        // `a <= b` falls back to `a < b or a == b`, and likewise for `>=`.
+        let strict = match op.token_type {
+            TokenType::LessEqual => Some(("__lt__", "__gt__")),
+            TokenType::GreaterEqual => Some(("__gt__", "__lt__")),
+            _ => None,
+        };
+        if let Some((method, reflected)) = strict {
+            let mut result = Expr::call_protocol(left, method, vec![right.clone()], output)?;
+            if result.is_none() {
+                result = Expr::call_protocol(right, reflected, vec![left.clone()], output)?;
+            }
+            if let Some(result) = result {
+                return Ok(Value::Boolean(
+                    Expr::is_truthy(result) || Expr::instances_equal(left, right, output)?,
+                ));
+            }
+        }
+        let message = format!(
+            "Operator '{}' is not defined for {} and {}; define {} to support it.",
+            op.lexeme, left, right, method
+        );
+        return Err(RuntimeError::at(op, message));
     }
 
-    fn interpret_plus(left_val: Value, right_val: Value) -> Value {
+    /// Equality through `__eq__` when either operand defines it, otherwise
+    /// instances are only equal to themselves.
+    fn instances_equal(
+        left: &Value,
+        right: &Value,
+        output: &mut Output,
+    ) -> Result<bool, RuntimeError> {
+        if let Some(result) = Expr::call_protocol(left, "__eq__", vec![right.clone()], output)? {
+            return Ok(Expr::is_truthy(result));
+        }
+        if let Some(result) = Expr::call_protocol(right, "__eq__", vec![left.clone()], output)? {
+            return Ok(Expr::is_truthy(result));
+        }
+        return Expr::is_equal(left, right);
+    }
+
+    fn interpret_plus(
+        op: &Token,
+        left_val: Value,
+        right_val: Value,
+    ) -> Result<Value, RuntimeError> {
         match (left_val, right_val) {
-            (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
             (Value::String(left), Value::String(right)) => {
-                Value::String(format!("{}{}", left, right))
+                Ok(Value::String(format!("{}{}", left, right)))
+            }
+            _ => {
+                let message = String::from("Operands must be two numbers or two strings.");
+                Err(RuntimeError::at(op, message))
             }
-            _ => panic!("Mismatched arguments"),
         }
     }
 
     pub fn is_truthy(value: Value) -> bool {
-        match value {
-            Value::Boolean(false) | Value::Nil => false,
-            _ => true,
-        }
+        !matches!(value, Value::Boolean(false) | Value::Nil)
     }
 
-    fn is_equal(left_val: Value, right_val: Value) -> bool {
-        match (left_val, right_val) {
+    /// Whether two values are equal: by content for lists, maps and
+    /// variants, and by identity for everything else. Comparing values
+    /// nested more than `MAX_NESTING` deep, as two lists that each contain
+    /// themselves are, is an error.
+    fn is_equal(left: &Value, right: &Value) -> Result<bool, RuntimeError> {
+        return Expr::is_equal_within(left, right, 0);
+    }
+
+    fn is_equal_within(left: &Value, right: &Value, depth: usize) -> Result<bool, RuntimeError> {
+        if depth == MAX_NESTING {
+            return Err(RuntimeError::new(format!(
+                "Cannot compare values nested more than {} deep.",
+                MAX_NESTING
+            )));
+        }
+        let elements_equal = |left: &[Value], right: &[Value]| -> Result<bool, RuntimeError> {
+            if left.len() != right.len() {
+                return Ok(false);
+            }
+            for (left, right) in left.iter().zip(right.iter()) {
+                if !Expr::is_equal_within(left, right, depth + 1)? {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        };
+        let equal = match (left, right) {
             (Value::Number(left), Value::Number(right)) => left == right,
             (Value::String(left), Value::String(right)) => left == right,
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            (Value::Native(left), Value::Native(right)) => Rc::ptr_eq(left, right),
+            (Value::Module(left), Value::Module(right)) => Rc::ptr_eq(left, right),
+            (Value::Generator(left), Value::Generator(right)) => Rc::ptr_eq(left, right),
+            (Value::List(left), Value::List(right)) => {
+                Rc::ptr_eq(left, right) || elements_equal(&left.borrow(), &right.borrow())?
+            }
+            (Value::Map(left), Value::Map(right)) => {
+                if Rc::ptr_eq(left, right) {
+                    return Ok(true);
+                }
+                let (left, right) = (left.borrow(), right.borrow());
+                if !left.keys().eq(right.keys()) {
+                    return Ok(false);
+                }
+                let left: Vec<Value> = left.values().cloned().collect();
+                let right: Vec<Value> = right.values().cloned().collect();
+                elements_equal(&left, &right)?
+            }
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            (Value::Enum(left), Value::Enum(right)) => Rc::ptr_eq(left, right),
+            (Value::Variant(left), Value::Variant(right)) => {
+                Rc::ptr_eq(&left.declaration, &right.declaration)
+                    && left.index == right.index
+                    && elements_equal(&left.payload, &right.payload)?
+            }
             (Value::Nil, Value::Nil) => true,
             _ => false,
-        }
+        };
+        return Ok(equal);
     }
 
-    fn get_number(value: Value) -> f32 {
+    fn get_number(method: &str, value: &Value) -> Result<f32, RuntimeError> {
         if let Value::Number(result) = value {
-            return result;
+            return Ok(*result);
+        }
+        return Err(RuntimeError::new(format!(
+            "{} expects a number, found {}.",
+            method, value
+        )));
+    }
+
+    fn get_string(method: &str, value: &Value) -> Result<String, RuntimeError> {
+        if let Value::String(result) = value {
+            return Ok(result.clone());
         }
-        panic!("Error: Expected Number")
+        return Err(RuntimeError::new(format!(
+            "{} expects a string, found {}.",
+            method, value
+        )));
     }
 }
@@ -0,0 +1,518 @@
+use crate::lox::interpreter::{RuntimeError, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// How deeply arrays and objects may nest, in text being parsed or values
+/// being written, before it is an error rather than a stack overflow.
+const MAX_DEPTH: usize = 512;
+
+/// Parses JSON text into Lox values: objects become maps, arrays lists, and
+/// `null` nil. Errors give the line and column where the text went wrong.
+pub fn parse(text: &str) -> Result<Value, RuntimeError> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        current: 0,
+        depth: 0,
+    };
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.current < parser.chars.len() {
+        return Err(parser.error("end of input"));
+    }
+    return Ok(value);
+}
+
+struct Parser {
+    chars: Vec<char>,
+    current: usize,
+    /// The arrays and objects open at the current character.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.current).copied();
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(
+            self.peek(),
+            Some(' ') | Some('\t') | Some('\n') | Some('\r')
+        ) {
+            self.current += 1;
+        }
+    }
+
+    /// An error at the current character, saying what was expected there.
+    fn error(&self, expected: &str) -> RuntimeError {
+        let before = &self.chars[..self.current.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        let found = match self.peek() {
+            Some(c) => format!("'{}'", c),
+            None => String::from("end of input"),
+        };
+        return RuntimeError::new(format!(
+            "Invalid JSON at line {}, column {}: expected {}, found {}.",
+            line, column, expected, found
+        ));
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), RuntimeError> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("'{}'", expected)));
+        }
+        self.current += 1;
+        return Ok(());
+    }
+
+    fn value(&mut self) -> Result<Value, RuntimeError> {
+        match self.peek() {
+            Some('{') | Some('[') if self.depth == MAX_DEPTH => {
+                Err(self.error("less deeply nested input"))
+            }
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('-') | Some('0'..='9') => self.number(),
+            Some('t') => self.keyword("true", Value::Boolean(true)),
+            Some('f') => self.keyword("false", Value::Boolean(false)),
+            Some('n') => self.keyword("null", Value::Nil),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Parser) -> Result<Value, RuntimeError>,
+    ) -> Result<Value, RuntimeError> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        return value;
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, RuntimeError> {
+        for expected in word.chars() {
+            if self.peek() != Some(expected) {
+                return Err(self.error(&format!("'{}'", word)));
+            }
+            self.current += 1;
+        }
+        return Ok(value);
+    }
+
+    fn object(&mut self) -> Result<Value, RuntimeError> {
+        self.expect('{')?;
+        let mut map = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.current += 1;
+            return Ok(Value::Map(Rc::new(RefCell::new(map))));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.value()?;
+            map.insert(key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.current += 1,
+                Some('}') => {
+                    self.current += 1;
+                    return Ok(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                _ => return Err(self.error("',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, RuntimeError> {
+        self.expect('[')?;
+        let mut elements = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.current += 1;
+            return Ok(Value::List(Rc::new(RefCell::new(elements))));
+        }
+        loop {
+            self.skip_whitespace();
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.current += 1,
+                Some(']') => {
+                    self.current += 1;
+                    return Ok(Value::List(Rc::new(RefCell::new(elements))));
+                }
+                _ => return Err(self.error("',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, RuntimeError> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.current += 1;
+                    return Ok(text);
+                }
+                Some('\\') => {
+                    self.current += 1;
+                    text.push(self.escape()?);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("a closing '\"'"));
+                }
+                Some(c) => {
+                    self.current += 1;
+                    text.push(c);
+                }
+                None => return Err(self.error("a closing '\"'")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, RuntimeError> {
+        let escaped = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.current += 1;
+                return self.unicode_escape();
+            }
+            _ => return Err(self.error("an escape sequence")),
+        };
+        self.current += 1;
+        return Ok(escaped);
+    }
+
+    /// The `XXXX` of a `\uXXXX` escape, combining a surrogate pair when the
+    /// first unit is a high surrogate.
+    fn unicode_escape(&mut self) -> Result<char, RuntimeError> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("a valid code point"));
+        }
+        for expected in ['\\', 'u'].iter() {
+            if self.peek() != Some(*expected) {
+                return Err(self.error("a low surrogate"));
+            }
+            self.current += 1;
+        }
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error("a low surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        return char::from_u32(code).ok_or_else(|| self.error("a valid code point"));
+    }
+
+    fn hex4(&mut self) -> Result<u32, RuntimeError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(self.error("a hex digit")),
+            }
+            self.current += 1;
+        }
+        return Ok(code);
+    }
+
+    fn number(&mut self) -> Result<Value, RuntimeError> {
+        let start = self.current;
+        if self.peek() == Some('-') {
+            self.current += 1;
+        }
+        match self.peek() {
+            Some('0') => self.current += 1,
+            Some('1'..='9') => self.digits(),
+            _ => return Err(self.error("a digit")),
+        }
+        if self.peek() == Some('.') {
+            self.current += 1;
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(self.error("a digit"));
+            }
+            self.digits();
+        }
+        if matches!(self.peek(), Some('e') | Some('E')) {
+            self.current += 1;
+            if matches!(self.peek(), Some('+') | Some('-')) {
+                self.current += 1;
+            }
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(self.error("a digit"));
+            }
+            self.digits();
+        }
+        let lexeme: String = self.chars[start..self.current].iter().collect();
+        let number: f32 = lexeme.parse().unwrap();
+        if !number.is_finite() {
+            // Reported at the start of the number, which is too big as a
+            // whole.
+            self.current = start;
+            return Err(self.error("a number small enough to hold"));
+        }
+        return Ok(Value::Number(number));
+    }
+
+    fn digits(&mut self) {
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.current += 1;
+        }
+    }
+}
+
+/// Writes `value` as JSON, putting each element on its own line indented
+/// by `indent` when it is not empty. Values with no JSON form, such as
+/// functions, non-finite numbers, lists that contain themselves or lists
+/// nested too deeply, are errors.
+pub fn stringify(value: &Value, indent: &str) -> Result<String, RuntimeError> {
+    let mut writer = Writer {
+        text: String::new(),
+        indent,
+        open: vec![],
+    };
+    writer.value(value, 0)?;
+    return Ok(writer.text);
+}
+
+struct Writer<'a> {
+    text: String,
+    indent: &'a str,
+    /// The lists and maps being written, to catch cycles.
+    open: Vec<*const ()>,
+}
+
+impl<'a> Writer<'a> {
+    fn value(&mut self, value: &Value, depth: usize) -> Result<(), RuntimeError> {
+        match value {
+            Value::Nil => self.text.push_str("null"),
+            Value::Boolean(value) => self.text.push_str(&value.to_string()),
+            Value::Number(number) if !number.is_finite() => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot convert {} to JSON.",
+                    number
+                )));
+            }
+            Value::Number(number) => self.text.push_str(&number.to_string()),
+            Value::String(text) => self.string(text),
+            Value::List(list) => {
+                self.enter(Rc::as_ptr(list) as *const ())?;
+                let list = list.borrow();
+                self.text.push('[');
+                for (i, element) in list.iter().enumerate() {
+                    self.separator(i, depth + 1);
+                    self.value(element, depth + 1)?;
+                }
+                self.close(!list.is_empty(), depth, ']');
+            }
+            Value::Map(map) => {
+                self.enter(Rc::as_ptr(map) as *const ())?;
+                let map = map.borrow();
+                self.text.push('{');
+                for (i, (key, element)) in map.iter().enumerate() {
+                    self.separator(i, depth + 1);
+                    self.string(key);
+                    self.text.push(':');
+                    if !self.indent.is_empty() {
+                        self.text.push(' ');
+                    }
+                    self.value(element, depth + 1)?;
+                }
+                self.close(!map.is_empty(), depth, '}');
+            }
+            other => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot convert {} to JSON.",
+                    other
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    fn enter(&mut self, pointer: *const ()) -> Result<(), RuntimeError> {
+        if self.open.len() == MAX_DEPTH {
+            return Err(RuntimeError::new(format!(
+                "Cannot convert lists or maps nested more than {} deep to JSON.",
+                MAX_DEPTH
+            )));
+        }
+        if self.open.contains(&pointer) {
+            return Err(RuntimeError::new(String::from(
+                "Cannot convert a list or map that contains itself to JSON.",
+            )));
+        }
+        self.open.push(pointer);
+        return Ok(());
+    }
+
+    fn separator(&mut self, index: usize, depth: usize) {
+        if index > 0 {
+            self.text.push(',');
+        }
+        self.newline(depth);
+    }
+
+    fn close(&mut self, nonempty: bool, depth: usize, bracket: char) {
+        self.open.pop();
+        if nonempty {
+            self.newline(depth);
+        }
+        self.text.push(bracket);
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if self.indent.is_empty() {
+            return;
+        }
+        self.text.push('\n');
+        for _ in 0..depth {
+            self.text.push_str(self.indent);
+        }
+    }
+
+    fn string(&mut self, text: &str) {
+        self.text.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => self.text.push_str("\\\""),
+                '\\' => self.text.push_str("\\\\"),
+                '\n' => self.text.push_str("\\n"),
+                '\r' => self.text.push_str("\\r"),
+                '\t' => self.text.push_str("\\t"),
+                c if (c as u32) < 0x20 => self.text.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.text.push(c),
+            }
+        }
+        self.text.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) -> String {
+        let value = parse(text).unwrap_or_else(|error| panic!("{}", error.message));
+        return stringify(&value, "").unwrap_or_else(|error| panic!("{}", error.message));
+    }
+
+    fn parse_error(text: &str) -> String {
+        return match parse(text) {
+            Ok(value) => panic!("parsed {:?} as {}", text, value),
+            Err(error) => error.message,
+        };
+    }
+
+    #[test]
+    fn parses_and_writes_values() {
+        assert_eq!(round_trip(" null "), "null");
+        assert_eq!(
+            round_trip("[true, false, -1.5e2, 0]"),
+            "[true,false,-150,0]"
+        );
+        assert_eq!(round_trip(r#"{"b": [], "a": {}}"#), r#"{"a":{},"b":[]}"#);
+        assert_eq!(round_trip(r#""\"\\\/\né😀""#), "\"\\\"\\\\/\\né😀\"");
+    }
+
+    #[test]
+    fn writes_with_an_indent() {
+        let value = parse(r#"{"a": [1, 2], "b": {}}"#).ok().unwrap();
+        assert_eq!(
+            stringify(&value, "  ").ok().unwrap(),
+            "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": {}\n}"
+        );
+    }
+
+    #[test]
+    fn reports_where_malformed_input_goes_wrong() {
+        assert_eq!(
+            parse_error(""),
+            "Invalid JSON at line 1, column 1: expected a value, found end of input."
+        );
+        assert_eq!(
+            parse_error("[1,\n 2 3]"),
+            "Invalid JSON at line 2, column 4: expected ',' or ']', found '3'."
+        );
+        assert_eq!(
+            parse_error(r#"{"a" 1}"#),
+            "Invalid JSON at line 1, column 6: expected ':', found '1'."
+        );
+        assert_eq!(
+            parse_error("{1: 2}"),
+            "Invalid JSON at line 1, column 2: expected a string key, found '1'."
+        );
+        assert_eq!(
+            parse_error("\"abc"),
+            "Invalid JSON at line 1, column 5: expected a closing '\"', found end of input."
+        );
+        assert_eq!(
+            parse_error(r#""\ud83d""#),
+            "Invalid JSON at line 1, column 8: expected a low surrogate, found '\"'."
+        );
+        assert_eq!(
+            parse_error("01"),
+            "Invalid JSON at line 1, column 2: expected end of input, found '1'."
+        );
+        assert_eq!(
+            parse_error("1."),
+            "Invalid JSON at line 1, column 3: expected a digit, found end of input."
+        );
+        assert_eq!(
+            parse_error("[1e400]"),
+            "Invalid JSON at line 1, column 2: expected a number small enough to hold, found '1'."
+        );
+        assert_eq!(
+            parse_error("-1e39"),
+            "Invalid JSON at line 1, column 1: expected a number small enough to hold, found '-'."
+        );
+        assert_eq!(
+            parse_error("nul"),
+            "Invalid JSON at line 1, column 4: expected 'null', found end of input."
+        );
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let deepest = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert_eq!(round_trip(&deepest), deepest);
+        assert_eq!(
+            parse_error(&"[".repeat(10000)),
+            "Invalid JSON at line 1, column 513: expected less deeply nested input, found '['."
+        );
+        let mut list = Value::List(Rc::new(RefCell::new(vec![])));
+        for _ in 0..MAX_DEPTH {
+            list = Value::List(Rc::new(RefCell::new(vec![list])));
+        }
+        assert!(stringify(&list, "").is_err());
+    }
+
+    #[test]
+    fn refuses_values_with_no_json_form() {
+        let list = Rc::new(RefCell::new(vec![]));
+        list.borrow_mut().push(Value::List(list.clone()));
+        assert!(stringify(&Value::List(list.clone()), "").is_err());
+        list.borrow_mut().clear();
+        assert!(stringify(&Value::Number(f32::NAN), "").is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use crate::lox::{
+    diagnostics::Diagnostic,
+    expr::Expr,
+    stmt::{FunctionDecl, Pattern, Stmt},
+    tokens::{Token, TokenType},
+};
+use std::collections::HashMap;
+
+/// A name declared in a scope, and whether anything reads it.
+struct Local {
+    name: Token,
+    kind: &'static str,
+    used: bool,
+}
+
+/// Looks over a parsed program for code that is legal but likely a mistake,
+/// reporting each as a warning with a code such as `unused-variable`. A
+/// comment `// lint-allow: code, ...` on the line of a warning, or alone on
+/// the line above it, silences warnings with those codes there.
+pub struct Linter<'a> {
+    /// The global scope first. Globals may be used by code run later, so
+    /// only locals are reported unused.
+    scopes: Vec<Vec<Local>>,
+    warnings: Vec<Diagnostic>,
+    allowed: HashMap<usize, Vec<&'a str>>,
+}
+
+impl<'a> Linter<'a> {
+    pub fn new(source: &'a str) -> Linter<'a> {
+        let mut allowed: HashMap<usize, Vec<&str>> = HashMap::new();
+        for (i, line) in source.lines().enumerate() {
+            let start = match line.find("// lint-allow:") {
+                Some(start) => start,
+                None => continue,
+            };
+            let codes: Vec<&str> = line[start + "// lint-allow:".len()..]
+                .split(',')
+                .map(str::trim)
+                .collect();
+            allowed.entry(i + 1).or_default().extend(codes.iter());
+            if line[..start].trim().is_empty() {
+                allowed.entry(i + 2).or_default().extend(codes);
+            }
+        }
+        return Linter {
+            scopes: vec![vec![]],
+            warnings: vec![],
+            allowed,
+        };
+    }
+
+    pub fn lint(mut self, program: &[Stmt]) -> Vec<Diagnostic> {
+        self.lint_block(program);
+        let allowed = self.allowed;
+        return self
+            .warnings
+            .into_iter()
+            .filter(|warning| match (warning.span, warning.code) {
+                (Some(span), Some(code)) => !allowed
+                    .get(&span.line)
+                    .is_some_and(|codes| codes.contains(&code)),
+                _ => true,
+            })
+            .collect();
+    }
+
+    /// Lints statements run one after another in the current scope.
+    /// Functions, classes and enums are declared first, as they may be used
+    /// by code before them that runs after they are defined.
+    fn lint_block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts.iter() {
+            match stmt {
+                Stmt::Function(declaration) => self.declare(&declaration.name, "Function"),
+                Stmt::Class(declaration) => self.declare(&declaration.name, "Class"),
+                Stmt::Enum(declaration) => self.declare(&declaration.name, "Enum"),
+                _ => {}
+            }
+        }
+        let mut returned = None;
+        for stmt in stmts.iter() {
+            if let Some(keyword) = returned.take() {
+                let unreachable = stmt.span().to(stmts.last().unwrap().span());
+                self.warn(
+                    "unreachable-code",
+                    Diagnostic::warning(Some(unreachable), String::from("Unreachable code."))
+                        .with_label(
+                            keyword,
+                            String::from("any code after this return is never run"),
+                        ),
+                );
+            }
+            if let Stmt::Return(keyword, _) = stmt {
+                returned = Some(keyword.span());
+            }
+            self.lint_stmt(stmt);
+        }
+    }
+
+    fn lint_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => self.lint_expr(expr),
+            Stmt::Print(_, values) | Stmt::Eprint(_, values) => {
+                for value in values.iter() {
+                    self.lint_expr(value);
+                }
+            }
+            Stmt::Var(_, name, _) => self.declare(name, "Variable"),
+            Stmt::InitialisedVar(_, name, _, initialiser) => {
+                self.lint_expr(initialiser);
+                self.declare(name, "Variable");
+            }
+            Stmt::Block(_, stmts) => {
+                self.scopes.push(vec![]);
+                self.lint_block(stmts);
+                self.end_scope();
+            }
+            Stmt::If(_, condition, consequent, alternate) => {
+                self.lint_condition(condition, false);
+                self.lint_stmt(consequent);
+                if let Some(alternate) = alternate {
+                    self.lint_stmt(alternate);
+                }
+            }
+            Stmt::While(_, condition, body) => {
+                self.lint_condition(condition, true);
+                self.lint_stmt(body);
+            }
+            Stmt::ForIn(_, variable, iterable, body) => {
+                self.lint_expr(iterable);
+                self.scopes.push(vec![]);
+                self.declare(variable, "Variable");
+                self.lint_stmt(body);
+                self.end_scope();
+            }
+            Stmt::Function(declaration) => self.lint_function(declaration),
+            Stmt::Class(declaration) => {
+                if let Some(superclass) = &declaration.superclass {
+                    self.use_name(superclass);
+                }
+                for method in declaration.methods.iter() {
+                    self.lint_function(method);
+                }
+            }
+            Stmt::Return(_, value) | Stmt::Yield(_, value) => {
+                if let Some(value) = value {
+                    self.lint_expr(value);
+                }
+            }
+            Stmt::Enum(_) => {}
+            Stmt::Match(_, scrutinee, arms) => {
+                self.lint_expr(scrutinee);
+                for arm in arms.iter() {
+                    self.scopes.push(vec![]);
+                    if let Pattern::Variant {
+                        enum_name,
+                        bindings,
+                        ..
+                    } = &arm.pattern
+                    {
+                        self.use_name(enum_name);
+                        for binding in bindings.iter().flatten() {
+                            self.declare(binding, "Variable");
+                        }
+                    }
+                    self.lint_stmt(&arm.body);
+                    self.end_scope();
+                }
+            }
+        }
+    }
+
+    fn lint_function(&mut self, declaration: &FunctionDecl) {
+        self.scopes.push(vec![]);
+        for param in declaration.params.iter() {
+            if let Some(default) = &param.default {
+                self.lint_expr(default);
+            }
+            self.declare(&param.name, "Parameter");
+        }
+        self.lint_block(&declaration.body);
+        self.end_scope();
+    }
+
+    /// Warns about an `if` or `while` condition that is the same every time.
+    /// `while (true)` is left alone as the way to write an endless loop.
+    fn lint_condition(&mut self, condition: &Expr, looping: bool) {
+        self.lint_expr(condition);
+        let truthy = match constant_truth(condition) {
+            Some(truthy) => truthy,
+            None => return,
+        };
+        if looping && truthy && matches!(condition, Expr::Literal(_)) {
+            return;
+        }
+        let message = format!("This condition is always {}.", truthy);
+        self.warn(
+            "constant-condition",
+            Diagnostic::warning(Some(condition.span()), message),
+        );
+    }
+
+    fn lint_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary { left, op, right } => {
+                self.lint_comparison(expr, left, op, right);
+                self.lint_expr(left);
+                self.lint_expr(right);
+            }
+            Expr::Grouping(_, expr) => self.lint_expr(expr),
+            Expr::Literal(_) | Expr::This(_) | Expr::Super { .. } => {}
+            Expr::Unary { right, .. } => self.lint_expr(right),
+            Expr::Var(name) => self.use_name(name),
+            Expr::Assignment(name, value) => {
+                if let Expr::Var(source) = &**value {
+                    if source.lexeme == name.lexeme {
+                        self.self_assignment(expr, &name.lexeme);
+                    }
+                }
+                self.lint_expr(value);
+            }
+            Expr::Call {
+                callee,
+                arguments,
+                named,
+                ..
+            } => {
+                self.lint_expr(callee);
+                for argument in arguments.iter() {
+                    self.lint_expr(argument);
+                }
+                for (_, argument) in named.iter() {
+                    self.lint_expr(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.lint_expr(object),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                if let (Expr::Var(target), Expr::Get { object, name: read }) = (&**object, &**value)
+                {
+                    if matches!(&**object, Expr::Var(source) if source.lexeme == target.lexeme)
+                        && read.lexeme == name.lexeme
+                    {
+                        let assigned = format!("{}.{}", target.lexeme, name.lexeme);
+                        self.self_assignment(expr, &assigned);
+                    }
+                }
+                self.lint_expr(object);
+                self.lint_expr(value);
+            }
+            Expr::List { elements, .. } => {
+                for element in elements.iter() {
+                    self.lint_expr(element);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.lint_expr(object);
+                self.lint_expr(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.lint_expr(object);
+                self.lint_expr(index);
+                self.lint_expr(value);
+            }
+        }
+    }
+
+    fn self_assignment(&mut self, expr: &Expr, assigned: &str) {
+        let message = format!("'{}' is assigned to itself.", assigned);
+        self.warn(
+            "self-assignment",
+            Diagnostic::warning(Some(expr.span()), message),
+        );
+    }
+
+    /// Warns about a comparison whose operands are known to be of kinds it
+    /// cannot usefully compare: ordering anything but numbers, which fails
+    /// when it runs, or testing values of different kinds for equality.
+    fn lint_comparison(&mut self, expr: &Expr, left: &Expr, op: &Token, right: &Expr) {
+        let (left, right) = match (kind(left), kind(right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return,
+        };
+        let message = match op.token_type {
+            TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+                if left != "a number" || right != "a number" =>
+            {
+                format!(
+                    "Comparing {} with {} using '{}' is a runtime error, as it only compares numbers.",
+                    left, right, op.lexeme
+                )
+            }
+            TokenType::EqualEqual if left != right => {
+                format!("{} is never equal to {}.", capitalise(left), right)
+            }
+            TokenType::BangEqual if left != right => {
+                format!("{} is always different from {}.", capitalise(left), right)
+            }
+            _ => return,
+        };
+        self.warn(
+            "impossible-comparison",
+            Diagnostic::warning(Some(expr.span()), message),
+        );
+    }
+
+    /// Declares `name` in the innermost scope, warning if it hides a local
+    /// of the same name from an enclosing one.
+    fn declare(&mut self, name: &Token, kind: &'static str) {
+        let depth = self.scopes.len();
+        if depth > 1 && !name.lexeme.starts_with('_') {
+            let shadowed = self.scopes[..depth - 1]
+                .iter()
+                .rev()
+                .flat_map(|scope| scope.iter().rev())
+                .find(|local| local.name.lexeme == name.lexeme)
+                .map(|local| local.name.span());
+            if let Some(shadowed) = shadowed {
+                let message = format!(
+                    "'{}' shadows a variable from an enclosing scope.",
+                    name.lexeme
+                );
+                self.warn(
+                    "shadowing",
+                    Diagnostic::warning(Some(name.span()), message)
+                        .with_label(shadowed, String::from("shadowed variable declared here")),
+                );
+            }
+        }
+        let scope = self.scopes.last_mut().unwrap();
+        if !scope.iter().any(|local| local.name.lexeme == name.lexeme) {
+            scope.push(Local {
+                name: name.clone(),
+                kind,
+                used: false,
+            });
+        }
+    }
+
+    fn use_name(&mut self, name: &Token) {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|local| local.name.lexeme == name.lexeme);
+        if let Some(local) = local {
+            local.used = true;
+        }
+    }
+
+    /// Leaves the innermost scope, warning about locals nothing read.
+    /// Names starting with `_` are taken to be unused on purpose.
+    fn end_scope(&mut self) {
+        for local in self.scopes.pop().unwrap() {
+            if local.used || local.name.lexeme.starts_with('_') {
+                continue;
+            }
+            let code = match local.kind {
+                "Parameter" => "unused-parameter",
+                _ => "unused-variable",
+            };
+            let message = format!("{} '{}' is never used.", local.kind, local.name.lexeme);
+            self.warn(
+                code,
+                Diagnostic::warning(Some(local.name.span()), message).with_help(format!(
+                    "Rename it to '_{}' if it is unused on purpose.",
+                    local.name.lexeme
+                )),
+            );
+        }
+    }
+
+    fn warn(&mut self, code: &'static str, warning: Diagnostic) {
+        self.warnings.push(Diagnostic {
+            code: Some(code),
+            ..warning
+        });
+    }
+}
+
+/// What kind of value `expr` is sure to produce, for the few expressions
+/// that can be told without running them.
+fn kind(expr: &Expr) -> Option<&'static str> {
+    match expr {
+        Expr::Literal(token) => match token.token_type {
+            TokenType::Number(_) => Some("a number"),
+            TokenType::String(_) => Some("a string"),
+            TokenType::True | TokenType::False => Some("a boolean"),
+            TokenType::Nil => Some("nil"),
+            _ => None,
+        },
+        Expr::Grouping(_, expr) => kind(expr),
+        Expr::List { .. } => Some("a list"),
+        Expr::Unary { op, right } => match op.token_type {
+            TokenType::Bang => Some("a boolean"),
+            _ => kind(right).filter(|kind| *kind == "a number"),
+        },
+        _ => None,
+    }
+}
+
+/// Whether `condition` is always truthy or always falsy, when that can be
+/// told without running it.
+fn constant_truth(condition: &Expr) -> Option<bool> {
+    match condition {
+        Expr::Literal(token) => match token.token_type {
+            TokenType::False | TokenType::Nil => Some(false),
+            _ => Some(true),
+        },
+        Expr::Grouping(_, expr) => constant_truth(expr),
+        Expr::List { .. } => Some(true),
+        Expr::Unary { op, right } if matches!(op.token_type, TokenType::Bang) => {
+            constant_truth(right).map(|truthy| !truthy)
+        }
+        _ => None,
+    }
+}
+
+fn capitalise(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
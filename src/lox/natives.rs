@@ -0,0 +1,935 @@
+use crate::lox::{
+    environment::Environment,
+    expr::Expr,
+    format,
+    generator::Iter,
+    interpreter::{Call, Class, Instance, Module, Native, RuntimeError, Value},
+    json,
+    random::Random,
+    sandbox::{Access, Sandbox},
+    Options, Output,
+};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// The most numbers `range` will put in a list.
+const MAX_RANGE: usize = 10_000_000;
+
+/// Defines the built in globals available to every script. The file natives
+/// are always defined but only succeed inside the directories the sandbox in
+/// `options` grants.
+pub fn define(environment: &mut Environment, options: &Options) {
+    environment.set_variable(String::from("math"), math());
+    environment.set_variable(String::from("json"), json());
+    let random = match options.seed {
+        Some(seed) => Random::new(seed),
+        None => Random::from_clock(),
+    };
+    let mut globals = files(Rc::new(options.sandbox.clone()));
+    globals.extend(random_natives(Rc::new(RefCell::new(random))));
+    globals.extend(reflection());
+    globals.push(("format", format_native()));
+    globals.extend(collections());
+    for (name, value) in globals {
+        environment.set_variable(String::from(name), value);
+    }
+}
+
+pub fn native<F>(name: &str, arity: RangeInclusive<usize>, function: F) -> Value
+where
+    F: Fn(Vec<Value>, &mut Output) -> Result<Value, RuntimeError> + 'static,
+{
+    return Value::Native(Rc::new(Native {
+        name: String::from(name),
+        arity,
+        named: false,
+        function: Box::new(function),
+    }));
+}
+
+/// A native that also takes named arguments, which arrive as a map after
+/// the positional arguments.
+pub fn native_named<F>(name: &str, arity: RangeInclusive<usize>, function: F) -> Value
+where
+    F: Fn(Vec<Value>, &mut Output) -> Result<Value, RuntimeError> + 'static,
+{
+    return Value::Native(Rc::new(Native {
+        name: String::from(name),
+        arity,
+        named: true,
+        function: Box::new(function),
+    }));
+}
+
+pub fn module(name: &str, members: Vec<(&str, Value)>) -> Value {
+    return Value::Module(Rc::new(Module {
+        name: String::from(name),
+        members: members
+            .into_iter()
+            .map(|(member, value)| (String::from(member), value))
+            .collect::<HashMap<_, _>>(),
+    }));
+}
+
+fn number(function: &str, value: &Value) -> Result<f32, RuntimeError> {
+    match value {
+        Value::Number(number) => Ok(*number),
+        other => Err(RuntimeError::new(format!(
+            "{} expects a number, found {}.",
+            function, other
+        ))),
+    }
+}
+
+fn string(function: &str, value: &Value) -> Result<String, RuntimeError> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        other => Err(RuntimeError::new(format!(
+            "{} expects a string, found {}.",
+            function, other
+        ))),
+    }
+}
+
+fn list(function: &str, value: &Value) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::List(list) => Ok(list.clone()),
+        other => Err(RuntimeError::new(format!(
+            "{} expects a list, found {}.",
+            function, other
+        ))),
+    }
+}
+
+fn instance(function: &str, value: &Value) -> Result<Rc<RefCell<Instance>>, RuntimeError> {
+    match value {
+        Value::Instance(instance) => Ok(instance.clone()),
+        other => Err(RuntimeError::new(format!(
+            "{} expects an instance, found {}.",
+            function, other
+        ))),
+    }
+}
+
+fn integer(function: &str, value: &Value) -> Result<i64, RuntimeError> {
+    let number = number(function, value)?;
+    if number.fract() != 0.0 || !number.is_finite() {
+        return Err(RuntimeError::new(format!(
+            "{} expects a whole number, found {}.",
+            function, number
+        )));
+    }
+    return Ok(number as i64);
+}
+
+fn io_error(action: &str, path: &str, error: std::io::Error) -> RuntimeError {
+    return RuntimeError::new(format!("Could not {} '{}': {}.", action, path, error));
+}
+
+/// A `math` function of one number.
+fn unary(name: &'static str, function: fn(f32) -> f32) -> (&'static str, Value) {
+    let qualified = format!("math.{}", name);
+    let value = native(name, 1..=1, move |arguments, _| {
+        Ok(Value::Number(function(number(&qualified, &arguments[0])?)))
+    });
+    return (name, value);
+}
+
+/// The `math` module. Every function takes numbers and raises a runtime
+/// error "math.f expects a number" when given anything else. Results follow IEEE
+/// floating point, so out of domain arguments such as `math.sqrt(-1)` or
+/// `math.log(0)` give `nan` or an infinity rather than an error; test for
+/// those with `math.is_nan` and `math.is_infinite`.
+///
+/// - `sqrt(x)`, `abs(x)`, `exp(x)`
+/// - `pow(x, y)`: `x` raised to the power `y`.
+/// - `floor(x)`, `ceil(x)`, `round(x)`: `round` rounds halves away from zero.
+/// - `min(x, ...)`, `max(x, ...)`: the smallest or largest of one or more
+///   numbers. A `nan` argument is ignored unless every argument is `nan`.
+/// - `sin(x)`, `cos(x)`, `tan(x)`, `asin(x)`, `acos(x)`, `atan(x)` in radians,
+///   and `atan2(y, x)`.
+/// - `log(x)` is the natural logarithm; `log2(x)` and `log10(x)` take other
+///   bases.
+/// - `is_nan(x)`, `is_infinite(x)` return booleans.
+/// - `pi` and `e` are constants.
+fn math() -> Value {
+    let mut members = vec![
+        unary("sqrt", f32::sqrt),
+        unary("abs", f32::abs),
+        unary("exp", f32::exp),
+        unary("floor", f32::floor),
+        unary("ceil", f32::ceil),
+        unary("round", f32::round),
+        unary("sin", f32::sin),
+        unary("cos", f32::cos),
+        unary("tan", f32::tan),
+        unary("asin", f32::asin),
+        unary("acos", f32::acos),
+        unary("atan", f32::atan),
+        unary("log", f32::ln),
+        unary("log2", f32::log2),
+        unary("log10", f32::log10),
+    ];
+    members.push((
+        "pow",
+        native("pow", 2..=2, |arguments, _| {
+            let base = number("math.pow", &arguments[0])?;
+            Ok(Value::Number(base.powf(number("math.pow", &arguments[1])?)))
+        }),
+    ));
+    members.push((
+        "atan2",
+        native("atan2", 2..=2, |arguments, _| {
+            let y = number("math.atan2", &arguments[0])?;
+            Ok(Value::Number(y.atan2(number("math.atan2", &arguments[1])?)))
+        }),
+    ));
+    members.push((
+        "min",
+        native("min", 1..=usize::MAX, |arguments, _| {
+            let numbers = arguments.iter().map(|value| number("math.min", value));
+            let numbers = numbers.collect::<Result<Vec<f32>, RuntimeError>>()?;
+            Ok(Value::Number(numbers.into_iter().fold(f32::NAN, f32::min)))
+        }),
+    ));
+    members.push((
+        "max",
+        native("max", 1..=usize::MAX, |arguments, _| {
+            let numbers = arguments.iter().map(|value| number("math.max", value));
+            let numbers = numbers.collect::<Result<Vec<f32>, RuntimeError>>()?;
+            Ok(Value::Number(numbers.into_iter().fold(f32::NAN, f32::max)))
+        }),
+    ));
+    members.push((
+        "is_nan",
+        native("is_nan", 1..=1, |arguments, _| {
+            Ok(Value::Boolean(
+                number("math.is_nan", &arguments[0])?.is_nan(),
+            ))
+        }),
+    ));
+    members.push((
+        "is_infinite",
+        native("is_infinite", 1..=1, |arguments, _| {
+            Ok(Value::Boolean(
+                number("math.is_infinite", &arguments[0])?.is_infinite(),
+            ))
+        }),
+    ));
+    members.push(("pi", Value::Number(std::f32::consts::PI)));
+    members.push(("e", Value::Number(std::f32::consts::E)));
+    return module("math", members);
+}
+
+/// The file natives. Each takes a path relative to the working directory and
+/// raises a runtime error when the sandbox does not grant access to it or
+/// the operation fails.
+///
+/// - `readFile(path)` returns the contents of a file as a string.
+/// - `writeFile(path, text)` creates or replaces a file.
+/// - `appendFile(path, text)` adds to the end of a file, creating it first
+///   if needed.
+/// - `listDir(path)` returns the sorted names of the entries of a directory.
+/// - `exists(path)` returns whether anything exists at a path.
+///
+/// Writing needs write access; everything else needs read access.
+fn files(sandbox: Rc<Sandbox>) -> Vec<(&'static str, Value)> {
+    let mut natives = vec![];
+    let granted = sandbox.clone();
+    natives.push((
+        "readFile",
+        native("readFile", 1..=1, move |arguments, _| {
+            let path = string("readFile", &arguments[0])?;
+            let resolved = granted.check(&path, Access::Read)?;
+            match fs::read_to_string(resolved) {
+                Ok(text) => Ok(Value::String(text)),
+                Err(error) => Err(io_error("read", &path, error)),
+            }
+        }),
+    ));
+    let granted = sandbox.clone();
+    natives.push((
+        "writeFile",
+        native("writeFile", 2..=2, move |arguments, _| {
+            let path = string("writeFile", &arguments[0])?;
+            let text = string("writeFile", &arguments[1])?;
+            let resolved = granted.check(&path, Access::Write)?;
+            match fs::write(resolved, text) {
+                Ok(()) => Ok(Value::Nil),
+                Err(error) => Err(io_error("write", &path, error)),
+            }
+        }),
+    ));
+    let granted = sandbox.clone();
+    natives.push((
+        "appendFile",
+        native("appendFile", 2..=2, move |arguments, _| {
+            let path = string("appendFile", &arguments[0])?;
+            let text = string("appendFile", &arguments[1])?;
+            let resolved = granted.check(&path, Access::Write)?;
+            let appended = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(resolved)
+                .and_then(|mut file| file.write_all(text.as_bytes()));
+            match appended {
+                Ok(()) => Ok(Value::Nil),
+                Err(error) => Err(io_error("append to", &path, error)),
+            }
+        }),
+    ));
+    let granted = sandbox.clone();
+    natives.push((
+        "listDir",
+        native("listDir", 1..=1, move |arguments, _| {
+            let path = string("listDir", &arguments[0])?;
+            let resolved = granted.check(&path, Access::Read)?;
+            let mut entries = fs::read_dir(resolved)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                        .collect::<std::io::Result<Vec<String>>>()
+                })
+                .map_err(|error| io_error("list", &path, error))?;
+            entries.sort();
+            let entries = entries.into_iter().map(Value::String).collect();
+            Ok(Value::List(Rc::new(RefCell::new(entries))))
+        }),
+    ));
+    natives.push((
+        "exists",
+        native("exists", 1..=1, move |arguments, _| {
+            let path = string("exists", &arguments[0])?;
+            let resolved = sandbox.check(&path, Access::Read)?;
+            Ok(Value::Boolean(resolved.exists()))
+        }),
+    ));
+    return natives;
+}
+
+/// The random natives, sharing one generator. Runs given the same seed,
+/// through `seed(n)` or the `--seed` option, make the same choices.
+///
+/// - `random()` returns a number in `[0, 1)`.
+/// - `randomInt(lo, hi)` returns a whole number from `lo` to `hi`
+///   inclusive; both must be whole and `lo` must not exceed `hi`.
+/// - `choice(list)` returns a random element of a non-empty list.
+/// - `shuffle(list)` reorders a list in place.
+/// - `seed(n)` restarts the sequence from the whole number `n`.
+fn random_natives(random: Rc<RefCell<Random>>) -> Vec<(&'static str, Value)> {
+    let mut natives = vec![];
+    let generator = random.clone();
+    natives.push((
+        "random",
+        native("random", 0..=0, move |_, _| {
+            Ok(Value::Number(generator.borrow_mut().next_f32()))
+        }),
+    ));
+    let generator = random.clone();
+    natives.push((
+        "randomInt",
+        native("randomInt", 2..=2, move |arguments, _| {
+            let lo = integer("randomInt", &arguments[0])?;
+            let hi = integer("randomInt", &arguments[1])?;
+            if lo > hi {
+                return Err(RuntimeError::new(format!(
+                    "randomInt expects lo <= hi, found {} and {}.",
+                    lo, hi
+                )));
+            }
+            // Bounds near the ends of i64 can be further apart than an i64
+            // can hold, so the range is worked out in i128.
+            let range = hi as i128 - lo as i128 + 1;
+            if range > u64::MAX as i128 {
+                return Err(RuntimeError::new(format!(
+                    "randomInt cannot pick between {} and {}: the range is too large.",
+                    lo, hi
+                )));
+            }
+            let offset = generator.borrow_mut().below(range as u64);
+            Ok(Value::Number((lo as i128 + offset as i128) as f32))
+        }),
+    ));
+    let generator = random.clone();
+    natives.push((
+        "choice",
+        native("choice", 1..=1, move |arguments, _| {
+            let list = list("choice", &arguments[0])?;
+            let list = list.borrow();
+            if list.is_empty() {
+                return Err(RuntimeError::new(String::from(
+                    "choice expects a non-empty list.",
+                )));
+            }
+            let index = generator.borrow_mut().below(list.len() as u64);
+            Ok(list[index as usize].clone())
+        }),
+    ));
+    let generator = random.clone();
+    natives.push((
+        "shuffle",
+        native("shuffle", 1..=1, move |arguments, _| {
+            let list = list("shuffle", &arguments[0])?;
+            let mut list = list.borrow_mut();
+            let mut generator = generator.borrow_mut();
+            for i in (1..list.len()).rev() {
+                let j = generator.below(i as u64 + 1) as usize;
+                list.swap(i, j);
+            }
+            Ok(Value::Nil)
+        }),
+    ));
+    natives.push((
+        "seed",
+        native("seed", 1..=1, move |arguments, _| {
+            let seed = integer("seed", &arguments[0])?;
+            random.borrow_mut().seed(seed as u64);
+            Ok(Value::Nil)
+        }),
+    ));
+    return natives;
+}
+
+/// The `json` module.
+///
+/// - `parse(text)` turns JSON into maps, lists, numbers, strings, booleans
+///   and nil. Malformed text is a runtime error giving the line and column.
+/// - `stringify(value, indent?)` turns those values back into JSON, on one
+///   line or, given an indent of a number of spaces or a string, with each
+///   element on its own line. As in JavaScript, only the first 10 spaces or
+///   characters of the indent are used. Other values, such as functions, are
+///   a runtime error.
+fn json() -> Value {
+    let parse = native("parse", 1..=1, |arguments, _| {
+        json::parse(&string("json.parse", &arguments[0])?)
+    });
+    let stringify = native("stringify", 1..=2, |arguments, _| {
+        let indent = match arguments.get(1) {
+            None | Some(Value::Nil) => String::new(),
+            Some(Value::String(indent)) => indent.chars().take(10).collect(),
+            Some(spaces) => " ".repeat(integer("json.stringify", spaces)?.clamp(0, 10) as usize),
+        };
+        Ok(Value::String(json::stringify(&arguments[0], &indent)?))
+    });
+    return module("json", vec![("parse", parse), ("stringify", stringify)]);
+}
+
+/// The name `type` gives for `value`: `num`, `str`, `bool`, `nil`, `list`,
+/// `map`, `fun`, `class`, `enum`, `generator` or `module`, matching the
+/// type annotations, or the class name of an instance and the enum name of
+/// a variant.
+fn type_name(value: &Value) -> String {
+    let name = match value {
+        Value::Number(_) => "num",
+        Value::String(_) => "str",
+        Value::Boolean(_) => "bool",
+        Value::Nil => "nil",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+        Value::Function(_) | Value::Native(_) | Value::VariantConstructor(_, _) => "fun",
+        Value::Class(_) => "class",
+        Value::Enum(_) => "enum",
+        Value::Generator(_) => "generator",
+        Value::Module(_) => "module",
+        Value::Instance(instance) => return instance.borrow().class.name.clone(),
+        Value::Variant(variant) => return variant.declaration.name.lexeme.clone(),
+    };
+    return String::from(name);
+}
+
+fn is_subclass(class: &Rc<Class>, ancestor: &Rc<Class>) -> bool {
+    if Rc::ptr_eq(class, ancestor) {
+        return true;
+    }
+    match &class.superclass {
+        Some(superclass) => is_subclass(superclass, ancestor),
+        None => false,
+    }
+}
+
+/// Natives for inspecting values at runtime. Functions also have `name`,
+/// `arity` and `params` properties.
+///
+/// - `type(x)` returns the name of the type of `x`, see `type_name`.
+/// - `isInstance(x, type)` checks whether `x` is an instance of a class or
+///   one of its subclasses, or a variant of an enum.
+/// - `fields(obj)` lists the names of the fields of an instance.
+/// - `hasField(obj, name)`, `getField(obj, name)` and
+///   `setField(obj, name, value)` access a field by name; getting a missing
+///   field is an error.
+/// - `methods(cls)` lists the names of the methods of a class, including
+///   inherited ones.
+fn reflection() -> Vec<(&'static str, Value)> {
+    let mut natives = vec![];
+    natives.push((
+        "type",
+        native("type", 1..=1, |arguments, _| {
+            Ok(Value::String(type_name(&arguments[0])))
+        }),
+    ));
+    natives.push((
+        "isInstance",
+        native("isInstance", 2..=2, |arguments, _| {
+            let result = match (&arguments[0], &arguments[1]) {
+                (Value::Instance(instance), Value::Class(class)) => {
+                    is_subclass(&instance.borrow().class, class)
+                }
+                (Value::Variant(variant), Value::Enum(declaration)) => {
+                    Rc::ptr_eq(&variant.declaration, declaration)
+                }
+                (_, Value::Class(_)) | (_, Value::Enum(_)) => false,
+                (_, other) => {
+                    return Err(RuntimeError::new(format!(
+                        "isInstance expects a class or enum, found {}.",
+                        other
+                    )));
+                }
+            };
+            Ok(Value::Boolean(result))
+        }),
+    ));
+    natives.push((
+        "fields",
+        native("fields", 1..=1, |arguments, _| {
+            let instance = instance("fields", &arguments[0])?;
+            let mut names: Vec<String> = instance.borrow().fields.keys().cloned().collect();
+            names.sort();
+            let names = names.into_iter().map(Value::String).collect();
+            Ok(Value::List(Rc::new(RefCell::new(names))))
+        }),
+    ));
+    natives.push((
+        "hasField",
+        native("hasField", 2..=2, |arguments, _| {
+            let instance = instance("hasField", &arguments[0])?;
+            let name = string("hasField", &arguments[1])?;
+            let found = instance.borrow().fields.contains_key(&name);
+            Ok(Value::Boolean(found))
+        }),
+    ));
+    natives.push((
+        "getField",
+        native("getField", 2..=2, |arguments, _| {
+            let instance = instance("getField", &arguments[0])?;
+            let name = string("getField", &arguments[1])?;
+            let field = instance.borrow().fields.get(&name).cloned();
+            field.ok_or_else(|| RuntimeError::new(format!("Undefined field '{}'.", name)))
+        }),
+    ));
+    natives.push((
+        "setField",
+        native("setField", 3..=3, |arguments, _| {
+            let instance = instance("setField", &arguments[0])?;
+            let name = string("setField", &arguments[1])?;
+            let value = arguments[2].clone();
+            instance.borrow_mut().fields.insert(name, value.clone());
+            Ok(value)
+        }),
+    ));
+    natives.push((
+        "methods",
+        native("methods", 1..=1, |arguments, _| {
+            let mut class = match &arguments[0] {
+                Value::Class(class) => Some(class.clone()),
+                other => {
+                    return Err(RuntimeError::new(format!(
+                        "methods expects a class, found {}.",
+                        other
+                    )));
+                }
+            };
+            let mut names = vec![];
+            while let Some(current) = class {
+                for name in current.methods.keys() {
+                    if !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+                class = current.superclass.clone();
+            }
+            names.sort();
+            let names = names.into_iter().map(Value::String).collect();
+            Ok(Value::List(Rc::new(RefCell::new(names))))
+        }),
+    ));
+    return natives;
+}
+
+/// `format(template, ...)` fills the placeholders in `template` from the
+/// positional and named arguments that follow it, as `format::format`
+/// describes: `format("{name:>8} {:.2}", total, name: "sum")`.
+fn format_native() -> Value {
+    return native_named("format", 1..=usize::MAX, |mut arguments, output| {
+        let named = match arguments.pop() {
+            Some(Value::Map(named)) => named,
+            _ => unreachable!("named natives receive their named arguments last"),
+        };
+        let template = string("format", &arguments[0])?;
+        let text = format::format(&template, &arguments[1..], &named.borrow(), output)?;
+        Ok(Value::String(text))
+    });
+}
+
+/// The elements of a list, the characters of a string, the keys of a map or
+/// the values a generator yields, in order.
+fn sequence(
+    function: &str,
+    value: &Value,
+    output: &mut Output,
+) -> Result<Vec<Value>, RuntimeError> {
+    match value {
+        Value::List(_) | Value::String(_) | Value::Map(_) | Value::Generator(_) => {
+            let mut iter = Iter::new(value.clone())?;
+            let mut elements = vec![];
+            while let Some(element) = iter.next(output)? {
+                elements.push(element);
+            }
+            return Ok(elements);
+        }
+        other => Err(RuntimeError::new(format!(
+            "{} expects a list, string, map or generator, found {}.",
+            function, other
+        ))),
+    }
+}
+
+fn callable(function: &str, value: &Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Function(_) | Value::Native(_) | Value::Class(_) | Value::VariantConstructor(..) => {
+            Ok(value.clone())
+        }
+        other => Err(RuntimeError::new(format!(
+            "{} expects a function, found {}.",
+            function, other
+        ))),
+    }
+}
+
+/// Calls back into Lox. Errors raised by the callback are passed on as they
+/// are, so they still point at the line that raised them.
+fn invoke(
+    callee: &Value,
+    arguments: Vec<Value>,
+    output: &mut Output,
+) -> Result<Value, RuntimeError> {
+    let call = Call {
+        callee: callee.clone(),
+        arguments,
+        named: vec![],
+    };
+    return Expr::call(call, output);
+}
+
+fn new_list(elements: Vec<Value>) -> Value {
+    return Value::List(Rc::new(RefCell::new(elements)));
+}
+
+/// The order `sort` uses when it is not given a comparator: numbers by
+/// value and strings by their characters.
+fn compare(left: &Value, right: &Value) -> Result<Ordering, RuntimeError> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => {
+            Ok(left.partial_cmp(right).unwrap_or(Ordering::Equal))
+        }
+        (Value::String(left), Value::String(right)) => Ok(left.cmp(right)),
+        (left, right) => Err(RuntimeError::new(format!(
+            "sort can only compare two numbers or two strings, found {} and {}.",
+            left, right
+        ))),
+    }
+}
+
+/// The order a comparator gives: a negative number puts `left` first, a
+/// positive one `right`, and zero keeps them as they are.
+fn compare_with(
+    comparator: &Value,
+    left: &Value,
+    right: &Value,
+    output: &mut Output,
+) -> Result<Ordering, RuntimeError> {
+    match invoke(comparator, vec![left.clone(), right.clone()], output)? {
+        Value::Number(order) => Ok(order.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+        other => Err(RuntimeError::new(format!(
+            "sort expects its comparator to return a number, found {}.",
+            other
+        ))),
+    }
+}
+
+/// A stable merge sort that stops at the first error `compare` raises,
+/// which `slice::sort_by` has no way to do.
+fn merge_sort<T, F>(elements: Vec<T>, compare: &mut F) -> Result<Vec<T>, RuntimeError>
+where
+    F: FnMut(&T, &T) -> Result<Ordering, RuntimeError>,
+{
+    if elements.len() <= 1 {
+        return Ok(elements);
+    }
+    let mut left = elements;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, compare)?;
+    let right = merge_sort(right, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if compare(r, l)? == Ordering::Less {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    return Ok(merged);
+}
+
+/// The collection natives. Each takes a list, a string (as its characters),
+/// a map (as its keys) or a generator, and those taking a function call it
+/// for each element in order.
+///
+/// - `map(seq, f)` returns a list of `f(element)`.
+/// - `filter(seq, f)` returns a list of the elements `f` is truthy for.
+/// - `reduce(seq, f, initial?)` folds with `f(accumulator, element)`,
+///   starting from `initial` or else the first element.
+/// - `each(seq, f)` calls `f(element)` and returns nil.
+/// - `any(seq, f?)` and `all(seq, f?)` test `f(element)`, or the element
+///   itself when there is no `f`, stopping as soon as the answer is known.
+/// - `zip(seq, ...)` returns lists of the elements at each position, as
+///   long as the shortest sequence.
+/// - `enumerate(seq)` returns `[index, element]` pairs.
+/// - `sort(seq, comparator?, key: f)` returns a new sorted list. The sort is
+///   stable. Without a comparator, numbers and strings sort in their natural
+///   order; a comparator returns a negative number, zero or a positive
+///   number, like `a - b`. With `key`, elements are ordered by `f(element)`.
+/// - `reverse(seq)` returns a reversed list, or a reversed string for a
+///   string.
+/// - `join(seq, separator?)` prints the elements and joins them with
+///   `separator`, an empty string by default.
+/// - `range(end)`, `range(start, end, step?)` returns the numbers from
+///   `start`, 0 by default, up to but not including `end`.
+fn collections() -> Vec<(&'static str, Value)> {
+    let mut natives = vec![];
+    natives.push((
+        "map",
+        native("map", 2..=2, |arguments, output| {
+            let f = callable("map", &arguments[1])?;
+            let mut results = vec![];
+            for element in sequence("map", &arguments[0], output)? {
+                results.push(invoke(&f, vec![element], output)?);
+            }
+            Ok(new_list(results))
+        }),
+    ));
+    natives.push((
+        "filter",
+        native("filter", 2..=2, |arguments, output| {
+            let f = callable("filter", &arguments[1])?;
+            let mut kept = vec![];
+            for element in sequence("filter", &arguments[0], output)? {
+                if Expr::is_truthy(invoke(&f, vec![element.clone()], output)?) {
+                    kept.push(element);
+                }
+            }
+            Ok(new_list(kept))
+        }),
+    ));
+    natives.push((
+        "reduce",
+        native("reduce", 2..=3, |arguments, output| {
+            let f = callable("reduce", &arguments[1])?;
+            let mut elements = sequence("reduce", &arguments[0], output)?.into_iter();
+            let mut accumulator = match arguments.get(2) {
+                Some(initial) => initial.clone(),
+                None => elements.next().ok_or_else(|| {
+                    RuntimeError::new(String::from(
+                        "reduce of an empty sequence needs an initial value.",
+                    ))
+                })?,
+            };
+            for element in elements {
+                accumulator = invoke(&f, vec![accumulator, element], output)?;
+            }
+            Ok(accumulator)
+        }),
+    ));
+    natives.push((
+        "each",
+        native("each", 2..=2, |arguments, output| {
+            let f = callable("each", &arguments[1])?;
+            for element in sequence("each", &arguments[0], output)? {
+                invoke(&f, vec![element], output)?;
+            }
+            Ok(Value::Nil)
+        }),
+    ));
+    for (name, wanted) in [("any", true), ("all", false)].iter().copied() {
+        natives.push((
+            name,
+            native(name, 1..=2, move |arguments, output| {
+                let f = match arguments.get(1) {
+                    Some(f) => Some(callable(name, f)?),
+                    None => None,
+                };
+                for element in sequence(name, &arguments[0], output)? {
+                    let test = match &f {
+                        Some(f) => invoke(f, vec![element], output)?,
+                        None => element,
+                    };
+                    if Expr::is_truthy(test) == wanted {
+                        return Ok(Value::Boolean(wanted));
+                    }
+                }
+                Ok(Value::Boolean(!wanted))
+            }),
+        ));
+    }
+    natives.push((
+        "zip",
+        native("zip", 1..=usize::MAX, |arguments, output| {
+            let mut sequences = vec![];
+            for argument in arguments.iter() {
+                sequences.push(sequence("zip", argument, output)?.into_iter());
+            }
+            let mut rows = vec![];
+            'rows: loop {
+                let mut row = vec![];
+                for elements in sequences.iter_mut() {
+                    match elements.next() {
+                        Some(element) => row.push(element),
+                        None => break 'rows,
+                    }
+                }
+                rows.push(new_list(row));
+            }
+            Ok(new_list(rows))
+        }),
+    ));
+    natives.push((
+        "enumerate",
+        native("enumerate", 1..=1, |arguments, output| {
+            let pairs = sequence("enumerate", &arguments[0], output)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, element)| new_list(vec![Value::Number(i as f32), element]))
+                .collect();
+            Ok(new_list(pairs))
+        }),
+    ));
+    natives.push((
+        "sort",
+        native_named("sort", 1..=2, |mut arguments, output| {
+            let key = match arguments.pop() {
+                Some(Value::Map(named)) => {
+                    let mut named = named.borrow().clone();
+                    let key = named.remove("key");
+                    if let Some(name) = named.keys().next() {
+                        return Err(RuntimeError::new(format!(
+                            "sort has no parameter named '{}'.",
+                            name
+                        )));
+                    }
+                    key
+                }
+                _ => unreachable!("named natives receive their named arguments last"),
+            };
+            let comparator = match arguments.get(1) {
+                Some(comparator) => Some(callable("sort", comparator)?),
+                None => None,
+            };
+            let elements = sequence("sort", &arguments[0], output)?;
+            // Pair each element with its key up front so that a key function
+            // runs once per element rather than once per comparison.
+            let mut keyed = vec![];
+            for element in elements {
+                let key = match &key {
+                    Some(key) => invoke(&callable("sort", key)?, vec![element.clone()], output)?,
+                    None => element.clone(),
+                };
+                keyed.push((key, element));
+            }
+            let sorted = merge_sort(keyed, &mut |(left, _), (right, _)| match &comparator {
+                Some(comparator) => compare_with(comparator, left, right, output),
+                None => compare(left, right),
+            })?;
+            let sorted = sorted.into_iter().map(|(_, element)| element).collect();
+            Ok(new_list(sorted))
+        }),
+    ));
+    natives.push((
+        "reverse",
+        native("reverse", 1..=1, |arguments, output| match &arguments[0] {
+            Value::String(text) => Ok(Value::String(text.chars().rev().collect())),
+            other => {
+                let mut elements = sequence("reverse", other, output)?;
+                elements.reverse();
+                Ok(new_list(elements))
+            }
+        }),
+    ));
+    natives.push((
+        "join",
+        native("join", 1..=2, |arguments, output| {
+            let separator = match arguments.get(1) {
+                Some(separator) => string("join", separator)?,
+                None => String::new(),
+            };
+            let mut parts = vec![];
+            for element in sequence("join", &arguments[0], output)? {
+                parts.push(element.stringify(output)?);
+            }
+            Ok(Value::String(parts.join(&separator)))
+        }),
+    ));
+    natives.push((
+        "range",
+        native("range", 1..=3, |arguments, _| {
+            let (start, end) = match arguments.len() {
+                1 => (0.0, number("range", &arguments[0])?),
+                _ => (
+                    number("range", &arguments[0])?,
+                    number("range", &arguments[1])?,
+                ),
+            };
+            let step = match arguments.get(2) {
+                Some(step) => number("range", step)?,
+                None => 1.0,
+            };
+            if step == 0.0 || !step.is_finite() {
+                return Err(RuntimeError::new(format!(
+                    "range expects a finite, non-zero step, found {}.",
+                    step
+                )));
+            }
+            if !start.is_finite() || !end.is_finite() {
+                return Err(RuntimeError::new(String::from(
+                    "range expects finite bounds.",
+                )));
+            }
+            let length = ((end as f64 - start as f64) / step as f64).ceil();
+            if length > MAX_RANGE as f64 {
+                return Err(RuntimeError::new(format!(
+                    "range can make at most {} numbers, but was asked for {}.",
+                    MAX_RANGE, length
+                )));
+            }
+            let mut numbers = vec![];
+            let mut i = 0;
+            loop {
+                let n = start + step * i as f32;
+                if (step > 0.0 && n >= end) || (step < 0.0 && n <= end) {
+                    break;
+                }
+                numbers.push(Value::Number(n));
+                i += 1;
+            }
+            Ok(new_list(numbers))
+        }),
+    ));
+    return natives;
+}
@@ -1,70 +1,325 @@
 use crate::lox::{
     expr::Expr,
-    stmt::Stmt,
-    tokens::{Token, TokenType},
+    stmt::{ClassDecl, EnumDecl, FunctionDecl, MatchArm, Param, Pattern, Stmt, VariantDecl},
+    tokens::{Span, Token, TokenType},
 };
+use std::rc::Rc;
+
+/// A syntax error at `token`, the first token the parser could not accept.
+/// The message says what was expected in its place.
+pub struct ParseError {
+    pub token: Token,
+    pub message: String,
+}
 
 pub struct Parser {
     tokens: Vec<Token>,
     curr: usize,
+    /// One entry per function body being parsed, recording whether it
+    /// contains a `yield` and is therefore a generator.
+    yields: Vec<bool>,
+    /// How many blocks enclose the current token.
+    depth: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, curr: 0 }
+        Parser {
+            tokens,
+            curr: 0,
+            yields: vec![],
+            depth: 0,
+            errors: vec![],
+        }
     }
 
-    pub fn parse(&mut self) -> Vec<Stmt> {
+    /// Parses the whole program. After a syntax error the parser skips to
+    /// the next statement and carries on, so every error in the source is
+    /// returned rather than just the first. Error tokens are parsed as
+    /// literals, leaving the scanner's errors to say what is wrong with them,
+    /// so a program can parse even though the scanner found errors in it.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut program = vec![];
-        while !matches!(self.peek().token_type, TokenType::Eof) {
-            program.push(self.declaration());
+        while !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                program.push(stmt);
+            }
+        }
+        // Errors at an error token follow from a problem the scanner has
+        // already reported, as do errors at the end when an unterminated
+        // string ran to it, so only the others are returned.
+        let ran_to_end = self.tokens.len() > 1
+            && matches!(
+                self.tokens[self.tokens.len() - 2].token_type,
+                TokenType::Error
+            );
+        let errors: Vec<ParseError> = std::mem::take(&mut self.errors)
+            .into_iter()
+            .filter(|error| match error.token.token_type {
+                TokenType::Error => false,
+                TokenType::Eof => !ran_to_end,
+                _ => true,
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        return Ok(program);
+    }
+
+    /// Parses a declaration, or records the error and synchronises when it
+    /// is malformed.
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.try_declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronise();
+                None
+            }
         }
-        return program;
     }
 
-    fn declaration(&mut self) -> Stmt {
+    /// Skips tokens until the start of the next statement: just past a `;`
+    /// or just before a keyword that begins one. Inside a block it also
+    /// stops before a `}`, leaving the block to close.
+    fn synchronise(&mut self) {
+        while !self.is_at_end() {
+            if self.depth > 0 && matches!(self.peek().token_type, TokenType::RightBrace) {
+                return;
+            }
+            if matches!(self.advance().token_type, TokenType::Semicolon) {
+                return;
+            }
+            if matches!(
+                self.peek().token_type,
+                TokenType::Class
+                    | TokenType::Enum
+                    | TokenType::Eprint
+                    | TokenType::Fun
+                    | TokenType::For
+                    | TokenType::If
+                    | TokenType::Match
+                    | TokenType::Print
+                    | TokenType::Return
+                    | TokenType::Var
+                    | TokenType::While
+                    | TokenType::Yield
+            ) {
+                return;
+            }
+        }
+    }
+
+    fn try_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek().span();
         if matches!(self.peek().token_type, TokenType::Var) {
             self.advance();
-            return self.variable_declaration();
+            return self.variable_declaration(start);
+        }
+        if matches!(self.peek().token_type, TokenType::Fun) {
+            self.advance();
+            return self.function_declaration(start);
+        }
+        if matches!(self.peek().token_type, TokenType::Enum) {
+            self.advance();
+            return self.enum_declaration(start);
+        }
+        if matches!(self.peek().token_type, TokenType::Class) {
+            self.advance();
+            return self.class_declaration(start);
         }
         return self.statement();
     }
 
-    fn variable_declaration(&mut self) -> Stmt {
-        let identifier = self.advance();
-        if !matches!(identifier.token_type, TokenType::Identifier(_)) {
-            panic!("Expected Identifier")
+    fn class_declaration(&mut self, start: Span) -> Result<Stmt, ParseError> {
+        let name = self.identifier("class name")?;
+        let mut superclass = None;
+        if matches!(self.peek().token_type, TokenType::Less) {
+            self.advance();
+            superclass = Some(self.identifier("superclass name")?);
+        }
+        self.consume(TokenType::LeftBrace, "'{' before class body")?;
+        let mut methods = vec![];
+        while !matches!(
+            self.peek().token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            let start = self.peek().span();
+            match self.function_declaration(start)? {
+                Stmt::Function(method) => methods.push(method),
+                _ => unreachable!(),
+            }
+        }
+        self.consume(TokenType::RightBrace, "'}' after class body")?;
+        return Ok(Stmt::Class(Rc::new(ClassDecl {
+            name,
+            superclass,
+            methods,
+            span: self.span_from(start),
+        })));
+    }
+
+    fn enum_declaration(&mut self, start: Span) -> Result<Stmt, ParseError> {
+        let name = self.identifier("enum name")?;
+        self.consume(TokenType::LeftBrace, "'{' before enum body")?;
+        let mut variants = vec![];
+        while !matches!(
+            self.peek().token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            let name = self.identifier("variant name")?;
+            let fields = if matches!(self.peek().token_type, TokenType::LeftParen) {
+                self.advance();
+                self.identifier_list("field name")?
+            } else {
+                vec![]
+            };
+            variants.push(VariantDecl { name, fields });
+            if !matches!(self.peek().token_type, TokenType::Comma) {
+                break;
+            }
+            self.advance();
         }
+        self.consume(TokenType::RightBrace, "'}' after enum body")?;
+        return Ok(Stmt::Enum(Rc::new(EnumDecl {
+            name,
+            variants,
+            span: self.span_from(start),
+        })));
+    }
+
+    /// Parses comma separated identifiers up to and including the closing `)`.
+    fn identifier_list(&mut self, expected: &str) -> Result<Vec<Token>, ParseError> {
+        let mut identifiers = vec![];
+        if !matches!(self.peek().token_type, TokenType::RightParen) {
+            loop {
+                identifiers.push(self.identifier(expected)?);
+                if !matches!(self.peek().token_type, TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.consume(TokenType::RightParen, "')'")?;
+        return Ok(identifiers);
+    }
+
+    fn variable_declaration(&mut self, start: Span) -> Result<Stmt, ParseError> {
+        let identifier = self.identifier("variable name")?;
+        let annotation = self.type_annotation()?;
         if matches!(self.peek().token_type, TokenType::Equal) {
             self.advance();
-            let initialiser = self.expression();
-            let res = Stmt::InitialisedVar(identifier, Box::new(initialiser));
-            if !matches!(self.advance().token_type, TokenType::Semicolon) {
-                panic!("Expected Semicolon")
+            let initialiser = self.expression()?;
+            let span = self.span_from(start);
+            self.consume(TokenType::Semicolon, "';' after variable declaration")?;
+            return Ok(Stmt::InitialisedVar(
+                span,
+                identifier,
+                annotation,
+                Box::new(initialiser),
+            ));
+        }
+        let span = self.span_from(start);
+        self.consume(TokenType::Semicolon, "';' after variable declaration")?;
+        return Ok(Stmt::Var(span, identifier, annotation));
+    }
+
+    fn function_declaration(&mut self, start: Span) -> Result<Stmt, ParseError> {
+        let name = self.identifier("function name")?;
+        self.consume(TokenType::LeftParen, "'(' after function name")?;
+        let mut params = vec![];
+        if !matches!(self.peek().token_type, TokenType::RightParen) {
+            loop {
+                if params.iter().any(|param: &Param| param.rest) {
+                    let message = String::from("Rest parameter must be last.");
+                    return Err(Parser::error(self.peek(), message));
+                }
+                let rest = matches!(self.peek().token_type, TokenType::Ellipsis);
+                if rest {
+                    self.advance();
+                }
+                let name = self.identifier("parameter name")?;
+                let annotation = self.type_annotation()?;
+                let mut default = None;
+                if matches!(self.peek().token_type, TokenType::Equal) {
+                    self.advance();
+                    default = Some(self.expression()?);
+                }
+                if rest && default.is_some() {
+                    let message = String::from("Rest parameter cannot have a default value.");
+                    return Err(Parser::error(name, message));
+                }
+                if !rest && default.is_none() && params.iter().any(|param| param.default.is_some())
+                {
+                    let message = String::from(
+                        "Parameter without a default cannot follow one with a default.",
+                    );
+                    return Err(Parser::error(name, message));
+                }
+                params.push(Param {
+                    name,
+                    annotation,
+                    default,
+                    rest,
+                });
+                if !matches!(self.peek().token_type, TokenType::Comma) {
+                    break;
+                }
+                self.advance();
             }
-            return res;
         }
+        self.consume(TokenType::RightParen, "')' after parameters")?;
+        let return_type = self.type_annotation()?;
+        self.consume(TokenType::LeftBrace, "'{' before function body")?;
+        self.yields.push(false);
+        let body = self.block();
+        let generator = self.yields.pop().unwrap();
+        return Ok(Stmt::Function(Rc::new(FunctionDecl {
+            name,
+            params,
+            return_type,
+            body: Rc::new(body?),
+            generator,
+            span: self.span_from(start),
+        })));
+    }
 
-        let res = Stmt::Var(identifier);
-        if !matches!(self.advance().token_type, TokenType::Semicolon) {
-            panic!("Expected Semicolon")
+    fn type_annotation(&mut self) -> Result<Option<Token>, ParseError> {
+        if !matches!(self.peek().token_type, TokenType::Colon) {
+            return Ok(None);
         }
-        return res;
+        self.advance();
+        if !matches!(
+            self.peek().token_type,
+            TokenType::Identifier(_) | TokenType::Nil | TokenType::Fun
+        ) {
+            return Err(self.expected("type name"));
+        }
+        return Ok(Some(self.advance()));
     }
 
-    fn statement(&mut self) -> Stmt {
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
         match self.peek().token_type {
-            TokenType::Print => {
-                self.advance();
-                let result = Stmt::Print(Box::new(self.expression()));
-                if !matches!(self.advance().token_type, TokenType::Semicolon) {
-                    panic!("Expected Semicolon")
+            TokenType::Print | TokenType::Eprint => {
+                let keyword = self.advance();
+                let mut values = vec![self.expression()?];
+                while matches!(self.peek().token_type, TokenType::Comma) {
+                    self.advance();
+                    values.push(self.expression()?);
                 }
-                return result;
+                let span = self.span_from(keyword.span());
+                self.consume(TokenType::Semicolon, "';' after value")?;
+                if matches!(keyword.token_type, TokenType::Eprint) {
+                    return Ok(Stmt::Eprint(span, values));
+                }
+                return Ok(Stmt::Print(span, values));
             }
             TokenType::LeftBrace => {
-                self.advance();
-                return Stmt::Block(Box::new(self.block()));
+                let brace = self.advance();
+                let stmts = self.block()?;
+                return Ok(Stmt::Block(self.span_from(brace.span()), Rc::new(stmts)));
             }
             TokenType::If => {
                 return self.if_stmt();
@@ -72,184 +327,433 @@ impl Parser {
             TokenType::While => {
                 return self.while_stmt();
             }
+            TokenType::Return => {
+                return self.return_stmt();
+            }
+            TokenType::Match => {
+                return self.match_stmt();
+            }
+            TokenType::For => {
+                return self.for_in_stmt();
+            }
+            TokenType::Yield => {
+                return self.yield_stmt();
+            }
             _ => {
-                let result = Stmt::Expression(Box::new(self.expression()));
-                if !matches!(self.advance().token_type, TokenType::Semicolon) {
-                    panic!("Expected Semicolon")
-                }
-                return result;
+                let result = Stmt::Expression(Box::new(self.expression()?));
+                self.consume(TokenType::Semicolon, "';' after expression")?;
+                return Ok(result);
             }
         }
     }
 
-    fn while_stmt(&mut self) -> Stmt {
-        if !matches!(self.advance().token_type, TokenType::While) {
-            panic!("")
+    fn match_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.consume(TokenType::Match, "'match'")?;
+        self.consume(TokenType::LeftParen, "'(' after 'match'")?;
+        let scrutinee = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after match value")?;
+        self.consume(TokenType::LeftBrace, "'{' before match arms")?;
+        let mut arms = vec![];
+        while !matches!(
+            self.peek().token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            let pattern = self.pattern()?;
+            self.consume(TokenType::FatArrow, "'=>' after pattern")?;
+            let body = Rc::new(self.statement()?);
+            arms.push(MatchArm { pattern, body });
         }
-        if !matches!(self.advance().token_type, TokenType::LeftParen) {
-            panic!("")
+        self.consume(TokenType::RightBrace, "'}' after match arms")?;
+        let span = self.span_from(keyword.span());
+        return Ok(Stmt::Match(span, Box::new(scrutinee), arms));
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        if self.peek().lexeme == "_" {
+            self.advance();
+            return Ok(Pattern::Wildcard);
         }
-        let condition = self.expression();
-        if !matches!(self.advance().token_type, TokenType::RightParen) {
-            panic!("")
+        let enum_name = self.identifier("enum name or '_' in pattern")?;
+        self.consume(TokenType::Dot, "'.' after enum name")?;
+        let variant = self.identifier("variant name in pattern")?;
+        let mut bindings = None;
+        if matches!(self.peek().token_type, TokenType::LeftParen) {
+            self.advance();
+            bindings = Some(self.identifier_list("binding name")?);
         }
-        let consequent = self.statement();
-        return Stmt::While(Box::new(condition), Box::new(consequent));
+        return Ok(Pattern::Variant {
+            enum_name: Box::new(enum_name),
+            variant: Box::new(variant),
+            bindings,
+        });
+    }
+
+    fn for_in_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.consume(TokenType::For, "'for'")?;
+        self.consume(TokenType::LeftParen, "'(' after 'for'")?;
+        let variable = self.identifier("loop variable")?;
+        self.consume(TokenType::In, "'in' after loop variable")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after loop iterable")?;
+        let body = self.statement()?;
+        return Ok(Stmt::ForIn(
+            keyword.span().to(body.span()),
+            variable,
+            Box::new(iterable),
+            Rc::new(body),
+        ));
     }
 
-    fn if_stmt(&mut self) -> Stmt {
-        if !matches!(self.advance().token_type, TokenType::If) {
-            panic!("")
+    fn yield_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.advance();
+        match self.yields.last_mut() {
+            Some(yields) => *yields = true,
+            None => {
+                let message = String::from("Cannot yield outside of a function.");
+                return Err(Parser::error(keyword, message));
+            }
         }
-        if !matches!(self.advance().token_type, TokenType::LeftParen) {
-            panic!("")
+        let mut value = None;
+        if !matches!(self.peek().token_type, TokenType::Semicolon) {
+            value = Some(Box::new(self.expression()?));
         }
-        let condition = self.expression();
-        if !matches!(self.advance().token_type, TokenType::RightParen) {
-            panic!("")
+        self.consume(TokenType::Semicolon, "';' after yield value")?;
+        return Ok(Stmt::Yield(keyword, value));
+    }
+
+    fn return_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.advance();
+        if self.yields.is_empty() {
+            let message = String::from("Cannot return from top-level code.");
+            return Err(Parser::error(keyword, message));
+        }
+        let mut value = None;
+        if !matches!(self.peek().token_type, TokenType::Semicolon) {
+            value = Some(Box::new(self.expression()?));
         }
-        let consequent = self.statement();
+        self.consume(TokenType::Semicolon, "';' after return value")?;
+        return Ok(Stmt::Return(keyword, value));
+    }
+
+    fn while_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.consume(TokenType::While, "'while'")?;
+        self.consume(TokenType::LeftParen, "'(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after condition")?;
+        let consequent = self.statement()?;
+        return Ok(Stmt::While(
+            keyword.span().to(consequent.span()),
+            Box::new(condition),
+            Rc::new(consequent),
+        ));
+    }
+
+    fn if_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.consume(TokenType::If, "'if'")?;
+        self.consume(TokenType::LeftParen, "'(' after 'if'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after condition")?;
+        let consequent = self.statement()?;
         if matches!(self.peek().token_type, TokenType::Else) {
             self.advance();
-            let alternate = self.statement();
-            return Stmt::If(
+            let alternate = self.statement()?;
+            return Ok(Stmt::If(
+                keyword.span().to(alternate.span()),
                 Box::new(condition),
-                Box::new(consequent),
-                Some(Box::new(alternate)),
-            );
+                Rc::new(consequent),
+                Some(Rc::new(alternate)),
+            ));
         }
-        return Stmt::If(Box::new(condition), Box::new(consequent), None);
+        return Ok(Stmt::If(
+            keyword.span().to(consequent.span()),
+            Box::new(condition),
+            Rc::new(consequent),
+            None,
+        ));
     }
 
-    fn block(&mut self) -> Vec<Stmt> {
+    /// Parses the declarations up to and including the closing `}`. Errors
+    /// inside the block are recorded and skipped like those at the top level.
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut res = vec![];
-        while !matches!(self.peek().token_type, TokenType::RightBrace) {
-            res.push(self.declaration());
-        }
-        if !matches!(self.advance().token_type, TokenType::RightBrace) {
-            panic!("Expected } at end of block")
+        self.depth += 1;
+        while !matches!(
+            self.peek().token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            if let Some(stmt) = self.declaration() {
+                res.push(stmt);
+            }
         }
-        return res;
+        self.depth -= 1;
+        self.consume(TokenType::RightBrace, "'}' at end of block")?;
+        return Ok(res);
     }
 
-    fn expression(&mut self) -> Expr {
+    fn expression(&mut self) -> Result<Expr, ParseError> {
         return self.assignment();
     }
 
-    fn assignment(&mut self) -> Expr {
-        let left = self.equality();
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let left = self.equality()?;
         if matches!(self.peek().token_type, TokenType::Equal) {
-            self.advance();
-            let right = self.assignment();
-            if let Expr::Var(id) = left {
-                return Expr::Assignment(id, Box::new(right));
+            let equals = self.advance();
+            let right = self.assignment()?;
+            match left {
+                Expr::Var(id) => return Ok(Expr::Assignment(id, Box::new(right))),
+                Expr::Get { object, name } => {
+                    return Ok(Expr::Set {
+                        object,
+                        name,
+                        value: Box::new(right),
+                    })
+                }
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                    ..
+                } => {
+                    return Ok(Expr::SetIndex {
+                        object,
+                        bracket,
+                        index,
+                        value: Box::new(right),
+                    })
+                }
+                _ => {
+                    let message = String::from("Invalid assignment target.");
+                    return Err(Parser::error(equals, message));
+                }
             }
-            panic!("Invalid Assignment")
         }
-        return left;
+        return Ok(left);
     }
 
-    fn equality(&mut self) -> Expr {
-        let mut expr = self.comparison();
+    fn equality(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.comparison()?;
         while matches!(
             self.peek().token_type,
             TokenType::BangEqual | TokenType::EqualEqual
         ) {
             let operator = self.advance();
-            let right = self.comparison();
+            let right = self.comparison()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
                 right: Box::new(right),
             }
         }
-        return expr;
+        return Ok(expr);
     }
 
-    fn comparison(&mut self) -> Expr {
-        let mut expr = self.term();
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.term()?;
         while matches!(
             self.peek().token_type,
             TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual
         ) {
             let operator = self.advance();
-            let right = self.term();
+            let right = self.term()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
                 right: Box::new(right),
             }
         }
-        return expr;
+        return Ok(expr);
     }
 
-    fn term(&mut self) -> Expr {
-        let mut expr = self.factor();
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.factor()?;
         while matches!(self.peek().token_type, TokenType::Plus | TokenType::Minus) {
             let operator = self.advance();
-            let right = self.factor();
+            let right = self.factor()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
                 right: Box::new(right),
             }
         }
-        return expr;
+        return Ok(expr);
     }
 
-    fn factor(&mut self) -> Expr {
-        let mut expr = self.unary();
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
         while matches!(self.peek().token_type, TokenType::Slash | TokenType::Star) {
             let operator = self.advance();
-            let right = self.unary();
+            let right = self.unary()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 op: operator,
                 right: Box::new(right),
             }
         }
-        return expr;
+        return Ok(expr);
     }
 
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         if matches!(self.peek().token_type, TokenType::Bang | TokenType::Minus) {
             let operator = self.advance();
-            let right = self.unary();
-            return Expr::Unary {
+            let right = self.unary()?;
+            return Ok(Expr::Unary {
                 op: operator,
                 right: Box::new(right),
-            };
+            });
+        }
+        return self.call();
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+        loop {
+            if matches!(self.peek().token_type, TokenType::Dot) {
+                self.advance();
+                let name = self.identifier("property name after '.'")?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+                continue;
+            }
+            if matches!(self.peek().token_type, TokenType::LeftBracket) {
+                let bracket = self.advance();
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "']' after index")?;
+                expr = Expr::Index {
+                    span: self.span_from(expr.span()),
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+                continue;
+            }
+            if !matches!(self.peek().token_type, TokenType::LeftParen) {
+                break;
+            }
+            self.advance();
+            expr = self.finish_call(expr)?;
+        }
+        return Ok(expr);
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = vec![];
+        let mut named = vec![];
+        if !matches!(self.peek().token_type, TokenType::RightParen) {
+            loop {
+                if matches!(self.peek().token_type, TokenType::Identifier(_))
+                    && matches!(self.peek_next().token_type, TokenType::Colon)
+                {
+                    let name = self.advance();
+                    self.advance();
+                    named.push((name, self.expression()?));
+                } else if named.is_empty() {
+                    arguments.push(self.expression()?);
+                } else {
+                    let message =
+                        String::from("Positional argument cannot follow named arguments.");
+                    return Err(Parser::error(self.peek(), message));
+                }
+                if !matches!(self.peek().token_type, TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
         }
-        return self.primary();
+        let paren = self.consume(TokenType::RightParen, "')' after arguments")?;
+        return Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+            named,
+        });
     }
 
-    fn primary(&mut self) -> Expr {
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         match self.peek().token_type {
             TokenType::True
             | TokenType::False
             | TokenType::Nil
             | TokenType::Number(_)
-            | TokenType::String(_) => Expr::Literal(self.advance()),
-            TokenType::Identifier(_) => Expr::Var(self.advance()),
-            TokenType::LeftParen => {
-                self.advance();
-                let expr = self.expression();
-                if !matches!(self.advance().token_type, TokenType::RightParen) {
-                    println!("Err: mismatched paren");
+            | TokenType::String(_) => Ok(Expr::Literal(self.advance())),
+            // Taken as a value in its place, so the rest of the expression
+            // is still checked. Nothing is run once the scanner has found an
+            // error.
+            TokenType::Error => Ok(Expr::Literal(self.advance())),
+            TokenType::Identifier(_) => Ok(Expr::Var(self.advance())),
+            TokenType::This => Ok(Expr::This(self.advance())),
+            TokenType::Super => {
+                let keyword = self.advance();
+                self.consume(TokenType::Dot, "'.' after 'super'")?;
+                let method = self.identifier("superclass method name")?;
+                Ok(Expr::Super { keyword, method })
+            }
+            TokenType::LeftBracket => {
+                let bracket = self.advance();
+                let mut elements = vec![];
+                while !matches!(
+                    self.peek().token_type,
+                    TokenType::RightBracket | TokenType::Eof
+                ) {
+                    elements.push(self.expression()?);
+                    if !matches!(self.peek().token_type, TokenType::Comma) {
+                        break;
+                    }
+                    self.advance();
                 }
-                return Expr::Grouping(Box::new(expr));
+                self.consume(TokenType::RightBracket, "']' after list elements")?;
+                return Ok(Expr::List {
+                    span: self.span_from(bracket.span()),
+                    bracket,
+                    elements,
+                });
             }
-            _ => {
-                println!("Err: Expected Literal");
-                Expr::Literal(Token::new(
-                    TokenType::String(String::from("Error")),
-                    String::from("Error"),
-                    0,
-                ))
+            TokenType::LeftParen => {
+                let paren = self.advance();
+                let expr = self.expression()?;
+                self.consume(TokenType::RightParen, "')' after expression")?;
+                let span = self.span_from(paren.span());
+                return Ok(Expr::Grouping(span, Box::new(expr)));
             }
+            _ => Err(self.expected("an expression")),
         }
     }
 
+    /// Consumes the next token if it is of the same kind as `token_type`,
+    /// ignoring any value it carries, or reports what was `expected`.
+    fn consume(&mut self, token_type: TokenType, expected: &str) -> Result<Token, ParseError> {
+        if std::mem::discriminant(&self.peek().token_type) != std::mem::discriminant(&token_type) {
+            return Err(self.expected(expected));
+        }
+        return Ok(self.advance());
+    }
+
+    fn identifier(&mut self, expected: &str) -> Result<Token, ParseError> {
+        return self.consume(TokenType::Identifier(String::new()), expected);
+    }
+
+    /// An error at the next token, which is not the `expected` one, saying
+    /// what was found instead.
+    fn expected(&mut self, expected: &str) -> ParseError {
+        let token = self.peek();
+        let found = match token.token_type {
+            TokenType::Eof => String::from("end of file"),
+            _ => format!("'{}'", token.lexeme),
+        };
+        let message = format!("Expected {}, found {}.", expected, found);
+        return Parser::error(token, message);
+    }
+
+    fn error(token: Token, message: String) -> ParseError {
+        return ParseError { token, message };
+    }
+
+    /// The span from `start` to the end of the token just consumed.
+    fn span_from(&mut self, start: Span) -> Span {
+        return start.to(self.previous().span());
+    }
+
     fn advance(&mut self) -> Token {
         if self.is_at_end() {
             return self.peek();
@@ -266,7 +770,93 @@ impl Parser {
         return self.tokens[self.curr].clone();
     }
 
+    fn peek_next(&mut self) -> Token {
+        if self.is_at_end() {
+            return self.peek();
+        }
+        return self.tokens[self.curr + 1].clone();
+    }
+
     fn is_at_end(&mut self) -> bool {
         return matches!(self.peek().token_type, TokenType::Eof);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::scanner::Scanner;
+
+    /// The statements of `source`, or each syntax error as its line, column
+    /// and message.
+    fn parse(source: &str) -> Result<Vec<String>, Vec<(usize, usize, String)>> {
+        let (tokens, _) = Scanner::new(String::from(source), 0).scan_tokens();
+        return match Parser::new(tokens).parse() {
+            Ok(program) => Ok(program.iter().map(|stmt| stmt.to_string()).collect()),
+            Err(errors) => Err(errors
+                .into_iter()
+                .map(|error| (error.token.line, error.token.column, error.message))
+                .collect()),
+        };
+    }
+
+    #[test]
+    fn parses_a_program() {
+        let program = parse("var a = 1;\nprint a + 2;\nfun f(x) { return x; }")
+            .ok()
+            .unwrap();
+        assert_eq!(program.len(), 3);
+        assert_eq!(program[2], "Fun f");
+    }
+
+    #[test]
+    fn refuses_a_return_outside_a_function() {
+        let errors = parse("return 1;\n{ return; }\nfun f() { return 2; }")
+            .err()
+            .unwrap();
+        let message = String::from("Cannot return from top-level code.");
+        assert_eq!(errors, vec![(1, 1, message.clone()), (2, 3, message)]);
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let errors = parse("var = 1;\nprint 1 +;\nvar ok = 2;\nfun f( { }\nprint ok")
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                (1, 5, String::from("Expected variable name, found '='.")),
+                (2, 10, String::from("Expected an expression, found ';'.")),
+                (4, 8, String::from("Expected parameter name, found '{'.")),
+                (
+                    5,
+                    9,
+                    String::from("Expected ';' after value, found end of file.")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_at_the_next_statement() {
+        let errors = parse("print (1;\nprint 2;\n}\nprint 3 3;").err().unwrap();
+        let lines: Vec<usize> = errors.iter().map(|(line, ..)| *line).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn leaves_error_tokens_to_the_scanner() {
+        assert_eq!(parse("var x = 12px + @;\nprint x;").ok().unwrap().len(), 2);
+        assert_eq!(parse("print \"abc").ok().unwrap().len(), 0);
+        let errors = parse("var x = @ 1;").err().unwrap();
+        assert_eq!(
+            errors,
+            vec![(
+                1,
+                11,
+                String::from("Expected ';' after variable declaration, found '1'.")
+            )]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+/// The generator behind the random natives: xorshift64* seeded through
+/// splitmix64, which is small, fast and gives the same sequence for the same
+/// seed on every platform.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        let mut random = Random { state: 0 };
+        random.seed(seed);
+        return random;
+    }
+
+    /// Seeds from the current time, for runs that were not given a seed.
+    pub fn from_clock() -> Random {
+        return Random::new(clock());
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        // splitmix64 spreads nearby seeds apart and never leaves the state at
+        // zero, which xorshift cannot escape.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        self.state = (z ^ (z >> 31)) | 1;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        return (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+    }
+
+    /// An index in `[0, bound)`. `bound` must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        return ((self.next_u64() as u128 * bound as u128) >> 64) as u64;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clock() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date)]
+    fn now() -> f64;
+}
+
+/// `SystemTime` is unavailable in the browser, so ask JavaScript instead.
+#[cfg(target_arch = "wasm32")]
+fn clock() -> u64 {
+    return now() as u64;
+}
@@ -0,0 +1,157 @@
+use crate::lox::interpreter::RuntimeError;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many symbolic links `resolve` follows before giving up.
+const MAX_LINKS: usize = 40;
+
+/// The directories scripts may touch through the file natives. Nothing is
+/// allowed by default; each grant covers a directory and everything below
+/// it. Paths are resolved before checking, so `..` and symbolic links cannot
+/// reach outside a granted directory.
+#[derive(Clone, Default)]
+pub struct Sandbox {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Sandbox {
+    /// Allows reading files and listing directories under `directory`, which
+    /// must exist.
+    pub fn allow_read<P: AsRef<Path>>(&mut self, directory: P) -> io::Result<()> {
+        self.read.push(directory.as_ref().canonicalize()?);
+        return Ok(());
+    }
+
+    /// Allows creating, overwriting and appending to files under `directory`,
+    /// which must exist.
+    pub fn allow_write<P: AsRef<Path>>(&mut self, directory: P) -> io::Result<()> {
+        self.write.push(directory.as_ref().canonicalize()?);
+        return Ok(());
+    }
+
+    /// Resolves `path` and checks it lies in a directory granted `access`.
+    pub fn check(&self, path: &str, access: Access) -> Result<PathBuf, RuntimeError> {
+        let (roots, verb, flag) = match access {
+            Access::Read => (&self.read, "read", "--allow-read"),
+            Access::Write => (&self.write, "write", "--allow-write"),
+        };
+        let denied = || {
+            RuntimeError::new(format!(
+                "Permission denied: cannot {} '{}'. Grant access with {}=DIR.",
+                verb, path, flag
+            ))
+        };
+        let resolved = Sandbox::resolve(Path::new(path)).map_err(|_| denied())?;
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            return Ok(resolved);
+        }
+        return Err(denied());
+    }
+
+    /// Canonicalizes `path`, which may name a file that does not exist yet as
+    /// long as its directory does. A dangling symbolic link is followed to
+    /// where it points, so writing through it is checked against the target
+    /// rather than the link.
+    fn resolve(path: &Path) -> io::Result<PathBuf> {
+        let mut path = path.to_path_buf();
+        for _ in 0..MAX_LINKS {
+            if let Ok(resolved) = path.canonicalize() {
+                return Ok(resolved);
+            }
+            let name = path
+                .file_name()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let parent = parent.canonicalize()?;
+            let link = parent.join(name);
+            match link.symlink_metadata() {
+                Ok(metadata) if metadata.file_type().is_symlink() => {}
+                _ => return Ok(link),
+            }
+            path = parent.join(link.read_link()?);
+        }
+        return Err(io::Error::other("too many levels of symbolic links"));
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    /// A fresh directory under the system temp directory, removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = std::env::temp_dir().join(format!("rlox-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("inside")).unwrap();
+            fs::create_dir_all(path.join("outside")).unwrap();
+            return Scratch(path);
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn allowed(result: Result<PathBuf, RuntimeError>) -> PathBuf {
+        return result.unwrap_or_else(|error| panic!("{}", error.message));
+    }
+
+    fn writable(scratch: &Scratch) -> Sandbox {
+        let mut sandbox = Sandbox::default();
+        sandbox.allow_write(scratch.0.join("inside")).unwrap();
+        return sandbox;
+    }
+
+    #[test]
+    fn allows_new_files_in_a_granted_directory() {
+        let scratch = Scratch::new("new-file");
+        let path = scratch.0.join("inside/new.txt");
+        let resolved = writable(&scratch).check(path.to_str().unwrap(), Access::Write);
+        assert!(allowed(resolved).ends_with("inside/new.txt"));
+    }
+
+    #[test]
+    fn refuses_a_dangling_link_that_points_outside() {
+        let scratch = Scratch::new("dangling");
+        let link = scratch.0.join("inside/link.txt");
+        symlink(scratch.0.join("outside/target.txt"), &link).unwrap();
+        let result = writable(&scratch).check(link.to_str().unwrap(), Access::Write);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn follows_a_dangling_link_that_stays_inside() {
+        let scratch = Scratch::new("inside-link");
+        let link = scratch.0.join("inside/link.txt");
+        symlink("target.txt", &link).unwrap();
+        let resolved = writable(&scratch).check(link.to_str().unwrap(), Access::Write);
+        assert!(allowed(resolved).ends_with("inside/target.txt"));
+    }
+
+    #[test]
+    fn refuses_links_that_loop() {
+        let scratch = Scratch::new("loop");
+        let link = scratch.0.join("inside/loop.txt");
+        symlink("loop.txt", &link).unwrap();
+        assert!(writable(&scratch)
+            .check(link.to_str().unwrap(), Access::Write)
+            .is_err());
+    }
+}
@@ -1,22 +1,38 @@
-use crate::lox;
-use crate::lox::tokens::{Token, TokenType};
+use crate::lox::{
+    diagnostics::Diagnostic,
+    tokens::{Span, Token, TokenType},
+};
 
 pub struct Scanner {
     source: String,
+    /// The number the source was given by `Diagnostics::add_source`, which
+    /// every span scanned carries.
+    source_id: usize,
     tokens: Vec<Token>,
     current: usize,
     start: usize,
     line: usize,
+    /// Where the current line begins, for working out columns.
+    line_start: usize,
+    /// The line and column of the token being scanned.
+    start_line: usize,
+    start_column: usize,
+    errors: Vec<Diagnostic>,
 }
 
 impl Scanner {
-    pub fn new(source: String) -> Scanner {
+    pub fn new(source: String, source_id: usize) -> Scanner {
         Scanner {
             source,
+            source_id,
             tokens: vec![],
             current: 0,
             start: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            errors: vec![],
         }
     }
 
@@ -24,47 +40,58 @@ impl Scanner {
         self.current >= self.source.len()
     }
 
+    /// The whole character starting at byte `offset`, which may be more than
+    /// one byte long outside ASCII.
+    fn char_at(&self, offset: usize) -> &str {
+        match self
+            .source
+            .get(offset..)
+            .and_then(|rest| rest.chars().next())
+        {
+            Some(c) => self.source.get(offset..offset + c.len_utf8()).unwrap(),
+            None => "\0",
+        }
+    }
+
     fn advance(&mut self) -> &str {
-        let c = self.source.get(self.current..self.current + 1).unwrap();
-        self.current += 1;
-        c
+        let start = self.current;
+        self.current += self.char_at(start).len();
+        self.source.get(start..self.current).unwrap()
     }
 
+    /// Consumes `expected` if the source continues with it.
     fn matches(&mut self, expected: &str) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.get(self.current..self.current + 1).unwrap() != expected {
+        if !self.source[self.current..].starts_with(expected) {
             return false;
         }
-        self.current += 1;
+        self.current += expected.len();
         return true;
     }
 
     fn peek_next(&self) -> &str {
-        if self.current + 1 >= self.source.len() {
+        if self.is_at_end() {
             return "\0";
         }
-        self.source.get(self.current + 1..self.current + 2).unwrap()
+        self.char_at(self.current + self.peek().len())
     }
 
     fn peek(&self) -> &str {
         if self.is_at_end() {
             return "\0";
         }
-        self.source.get(self.current..self.current + 1).unwrap()
+        self.char_at(self.current)
     }
 
     fn string(&mut self) {
         while self.peek() != "\"" && !self.is_at_end() {
-            if self.peek() == "\n" {
-                self.line += 1;
+            if self.advance() == "\n" {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            lox::error(self.line, "Unterminated String Literal.")
+            self.error_token(String::from("Unterminated string."));
+            return;
         }
 
         self.advance();
@@ -76,12 +103,12 @@ impl Scanner {
 
     fn is_digit(c: &str) -> bool {
         let c = c.chars().next().unwrap();
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
     fn is_alpha(c: &str) -> bool {
         let c = c.chars().next().unwrap();
-        c >= 'a' && c <= 'z' || c >= 'A' && c <= 'Z' || c == '_'
+        c.is_ascii_alphabetic() || c == '_'
     }
 
     fn is_alphanum(c: &str) -> bool {
@@ -89,13 +116,48 @@ impl Scanner {
     }
 
     fn number(&mut self) {
-        while Scanner::is_digit(self.peek())
-            || (self.peek() == "." && Scanner::is_digit(self.peek_next()))
-        {
-            self.advance();
+        let mut fraction = false;
+        let mut malformed = false;
+        loop {
+            if Scanner::is_digit(self.peek()) {
+                self.advance();
+            } else if self.peek() == "." && Scanner::is_digit(self.peek_next()) {
+                // A second fraction, as in `1.2.3`.
+                malformed |= fraction;
+                fraction = true;
+                self.advance();
+            } else if Scanner::is_alpha(self.peek()) {
+                // Letters run on from the digits, as in `12px`.
+                malformed = true;
+                self.advance();
+            } else {
+                break;
+            }
         }
         let lexeme = String::from(self.source.get(self.start..self.current).unwrap());
-        self.add_token(TokenType::Number(lexeme.parse().unwrap()))
+        match lexeme.parse() {
+            Ok(value) if !malformed => self.add_token(TokenType::Number(value)),
+            _ => self.error_token(format!("Malformed number '{}'.", lexeme)),
+        }
+    }
+
+    /// Skips a `/* */` comment, which may hold others, once its `/*` has
+    /// been consumed.
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error(String::from("Unterminated comment."));
+                return;
+            }
+            if self.matches("*/") {
+                depth -= 1;
+            } else if self.matches("/*") {
+                depth += 1;
+            } else if self.advance() == "\n" {
+                self.newline();
+            }
+        }
     }
 
     fn identifier(&mut self) {
@@ -111,10 +173,14 @@ impl Scanner {
             "and" => self.add_token(TokenType::And),
             "class" => self.add_token(TokenType::Class),
             "else" => self.add_token(TokenType::Else),
+            "enum" => self.add_token(TokenType::Enum),
+            "eprint" => self.add_token(TokenType::Eprint),
             "false" => self.add_token(TokenType::False),
             "for" => self.add_token(TokenType::For),
             "fun" => self.add_token(TokenType::Fun),
             "if" => self.add_token(TokenType::If),
+            "in" => self.add_token(TokenType::In),
+            "match" => self.add_token(TokenType::Match),
             "nil" => self.add_token(TokenType::Nil),
             "or" => self.add_token(TokenType::Or),
             "print" => self.add_token(TokenType::Print),
@@ -124,6 +190,7 @@ impl Scanner {
             "true" => self.add_token(TokenType::True),
             "var" => self.add_token(TokenType::Var),
             "while" => self.add_token(TokenType::While),
+            "yield" => self.add_token(TokenType::Yield),
             _ => self.add_token(TokenType::Identifier(lexeme)),
         }
     }
@@ -135,11 +202,22 @@ impl Scanner {
             ")" => self.add_token(TokenType::RightParen),
             "{" => self.add_token(TokenType::LeftBrace),
             "}" => self.add_token(TokenType::RightBrace),
+            "[" => self.add_token(TokenType::LeftBracket),
+            "]" => self.add_token(TokenType::RightBracket),
             "," => self.add_token(TokenType::Comma),
-            "." => self.add_token(TokenType::Dot),
+            "." => {
+                if self.peek() == "." && self.peek_next() == "." {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::Ellipsis)
+                } else {
+                    self.add_token(TokenType::Dot)
+                }
+            }
             "-" => self.add_token(TokenType::Minus),
             "+" => self.add_token(TokenType::Plus),
             ";" => self.add_token(TokenType::Semicolon),
+            ":" => self.add_token(TokenType::Colon),
             "*" => self.add_token(TokenType::Star),
             "!" => {
                 let token = if self.matches("=") {
@@ -152,6 +230,8 @@ impl Scanner {
             "=" => {
                 let token = if self.matches("=") {
                     TokenType::EqualEqual
+                } else if self.matches(">") {
+                    TokenType::FatArrow
                 } else {
                     TokenType::Equal
                 };
@@ -178,12 +258,14 @@ impl Scanner {
                     while self.peek() != "\n" && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.matches("*") {
+                    self.block_comment();
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             }
             "\"" => self.string(),
-            "\n" => self.line += 1,
+            "\n" => self.newline(),
             " " | "\r" | "\t" => {}
             _ => {
                 if Scanner::is_digit(c) {
@@ -191,25 +273,169 @@ impl Scanner {
                 } else if Scanner::is_alpha(c) {
                     self.identifier();
                 } else {
-                    lox::error(self.line, "Unexpected Character.")
+                    let message = format!("Unexpected character '{}'.", c);
+                    self.error_token(message);
                 }
             }
         };
     }
 
+    /// Moves on to the next line, just after the `\n` that ends this one.
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    /// The column of byte `offset` on the current line.
+    fn column(&self, offset: usize) -> usize {
+        return self.source[self.line_start..offset].chars().count() + 1;
+    }
+
+    /// Marks the current character as the start of the next token.
+    fn begin_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column(self.start);
+    }
+
+    /// Where the token being scanned is, up to the current character.
+    fn span(&self) -> Span {
+        return Span {
+            source: self.source_id,
+            start: self.start,
+            end: self.current,
+            line: self.start_line,
+            column: self.start_column,
+        };
+    }
+
+    fn error(&mut self, message: String) {
+        let span = self.span();
+        self.errors
+            .push(Diagnostic::error(Some(span), message).with_code("lexical-error"));
+    }
+
+    /// Reports the text scanned so far as not making a token, and stands an
+    /// error token in for it so the parser can carry on past it.
+    fn error_token(&mut self, message: String) {
+        self.error(message);
+        self.add_token(TokenType::Error);
+    }
+
     fn add_token(&mut self, token_type: TokenType) {
         let lexeme = String::from(self.source.get(self.start..self.current).unwrap());
-        self.tokens.push(Token::new(token_type, lexeme, self.line));
+        self.tokens
+            .push(Token::new(token_type, lexeme, self.span()));
     }
 
-    pub fn scan_tokens(mut self) -> Vec<Token> {
+    /// Splits the source into tokens, along with an error for each piece
+    /// of it that does not make one: an unexpected character, an
+    /// unterminated string or comment, or a malformed number. Every input
+    /// scans to the end, with an error token in place of each bad piece
+    /// other than a comment.
+    pub fn scan_tokens(mut self) -> (Vec<Token>, Vec<Diagnostic>) {
         while !self.is_at_end() {
-            self.start = self.current;
+            self.begin_token();
             self.scan_token();
         }
 
+        self.begin_token();
         self.tokens
-            .push(Token::new(TokenType::Eof, String::from(""), self.line));
-        self.tokens
+            .push(Token::new(TokenType::Eof, String::from(""), self.span()));
+        return (self.tokens, self.errors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each token of `source` as its type and lexeme, and each error's
+    /// message.
+    fn scan(source: &str) -> (Vec<String>, Vec<String>) {
+        let (tokens, errors) = Scanner::new(String::from(source), 0).scan_tokens();
+        let tokens = tokens
+            .iter()
+            .map(|token| {
+                format!("{} {}", token.token_type, token.lexeme)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+        let errors = errors.into_iter().map(|error| error.message).collect();
+        return (tokens, errors);
+    }
+
+    #[test]
+    fn scans_tokens() {
+        let (tokens, errors) = scan("var x = 1.5 >= \"a\"; // comment\n...=>");
+        assert_eq!(
+            tokens,
+            vec![
+                "Var var",
+                "Identifier(\"x\") x",
+                "Equal =",
+                "Number(1.5) 1.5",
+                "GreaterEqual >=",
+                "String(\"a\") \"a\"",
+                "Semicolon ;",
+                "Ellipsis ...",
+                "FatArrow =>",
+                "Eof",
+            ]
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn tracks_lines_columns_and_offsets() {
+        let source = "é = 1;\n  /* a\n b */ x";
+        let (tokens, _) = Scanner::new(String::from(source), 7).scan_tokens();
+        let x = &tokens[4];
+        assert_eq!(x.lexeme, "x");
+        assert_eq!((x.line, x.column), (3, 7));
+        assert_eq!(&source[x.start..x.end], "x");
+        assert_eq!(x.span().source, 7);
+        assert_eq!((tokens[1].line, tokens[1].column), (1, 3));
+    }
+
+    #[test]
+    fn skips_nested_block_comments() {
+        let (tokens, errors) = scan("/* a /* b */ c */ 1");
+        assert_eq!(tokens, vec!["Number(1.0) 1", "Eof"]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn stands_error_tokens_in_for_lexical_errors() {
+        let (tokens, errors) = scan("12px 1.2.3 @ \"abc");
+        assert_eq!(
+            tokens,
+            vec!["Error 12px", "Error 1.2.3", "Error @", "Error \"abc", "Eof"]
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "Malformed number '12px'.",
+                "Malformed number '1.2.3'.",
+                "Unexpected character '@'.",
+                "Unterminated string.",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_an_unterminated_comment() {
+        let (tokens, errors) = scan("1 /* a /* b */");
+        assert_eq!(tokens, vec!["Number(1.0) 1", "Eof"]);
+        assert_eq!(errors, vec!["Unterminated comment."]);
+    }
+
+    #[test]
+    fn scans_any_input_to_the_end() {
+        for source in ["\"", "/*", "1.", ".", "é", "\0", "0x", "1e", "\"\\"].iter() {
+            let (tokens, _) = scan(source);
+            assert_eq!(tokens.last().unwrap(), "Eof");
+        }
     }
 }
@@ -1,86 +1,398 @@
 use crate::lox::{
     environment::Environment,
     expr::Expr,
-    interpreter::Value,
-    tokens::{Token, TokenType},
+    generator::Iter,
+    interpreter::{Call, Class, Function, RuntimeError, Value},
+    tokens::{Span, Token},
+    Output,
 };
+use std::cell::RefCell;
 use std::fmt;
 use std::fmt::Write;
 use std::rc::Rc;
 
+pub struct Param {
+    pub name: Token,
+    pub annotation: Option<Token>,
+    pub default: Option<Expr>,
+    pub rest: bool,
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.rest {
+            write!(f, "...")?;
+        }
+        write!(f, "{}", self.name.lexeme)?;
+        if let Some(annotation) = &self.annotation {
+            write!(f, ": {}", annotation.lexeme)?;
+        }
+        match &self.default {
+            Some(Expr::Literal(literal)) => write!(f, " = {}", literal.lexeme),
+            Some(_) => write!(f, " = ..."),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Param>,
+    pub return_type: Option<Token>,
+    pub body: Rc<Vec<Stmt>>,
+    pub generator: bool,
+    pub span: Span,
+}
+
+impl FunctionDecl {
+    /// The declaration as written, e.g. `draw(x: num, y = 0, ...rest)`, for
+    /// use in error messages.
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|param| param.to_string()).collect();
+        return format!("{}({})", self.name.lexeme, params.join(", "));
+    }
+}
+
+pub struct ClassDecl {
+    pub name: Token,
+    pub superclass: Option<Token>,
+    pub methods: Vec<Rc<FunctionDecl>>,
+    pub span: Span,
+}
+
+pub struct VariantDecl {
+    pub name: Token,
+    pub fields: Vec<Token>,
+}
+
+pub struct EnumDecl {
+    pub name: Token,
+    pub variants: Vec<VariantDecl>,
+    pub span: Span,
+}
+
+impl EnumDecl {
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        return self
+            .variants
+            .iter()
+            .position(|variant| variant.name.lexeme == name);
+    }
+}
+
+pub enum Pattern {
+    Wildcard,
+    Variant {
+        enum_name: Box<Token>,
+        variant: Box<Token>,
+        bindings: Option<Vec<Token>>,
+    },
+}
+
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Rc<Stmt>,
+}
+
+/// A statement. Those that do not keep the tokens they start and end with,
+/// such as the `print` keyword or a block's braces, keep their span instead.
 pub enum Stmt {
     Expression(Box<Expr>),
-    Print(Box<Expr>),
-    Var(Token),
-    InitialisedVar(Token, Box<Expr>),
-    Block(Box<Vec<Stmt>>),
-    If(Box<Expr>, Box<Stmt>, Option<Box<Stmt>>),
-    While(Box<Expr>, Box<Stmt>),
+    Print(Span, Vec<Expr>),
+    /// Like `Print`, but written to the output's error text, which the CLI
+    /// shows on standard error.
+    Eprint(Span, Vec<Expr>),
+    Var(Span, Token, Option<Token>),
+    InitialisedVar(Span, Token, Option<Token>, Box<Expr>),
+    Block(Span, Rc<Vec<Stmt>>),
+    If(Span, Box<Expr>, Rc<Stmt>, Option<Rc<Stmt>>),
+    While(Span, Box<Expr>, Rc<Stmt>),
+    ForIn(Span, Token, Box<Expr>, Rc<Stmt>),
+    Function(Rc<FunctionDecl>),
+    Class(Rc<ClassDecl>),
+    Return(Token, Option<Box<Expr>>),
+    Enum(Rc<EnumDecl>),
+    Match(Span, Box<Expr>, Vec<MatchArm>),
+    Yield(Token, Option<Box<Expr>>),
+}
+
+pub enum Flow {
+    Normal,
+    Return(Value),
+    TailCall(Call),
 }
 
 impl fmt::Display for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Stmt::Expression(expr) => write!(f, "Expr {}", expr),
-            Stmt::Print(expr) => write!(f, "Print {}", expr),
-            Stmt::Var(token) => write!(f, "Var {}", token),
-            Stmt::InitialisedVar(token, expr) => write!(f, "Init Var {} {}", token, expr),
-            Stmt::Block(_) => write!(f, "Block Statement"),
-            Stmt::If(_, _, _) => write!(f, "If Statement"),
-            Stmt::While(_, _) => write!(f, "While Statment"),
-            _ => panic!("Unsupported Statement"),
+            Stmt::Print(_, values) => write!(f, "Print {}", Stmt::join(values)),
+            Stmt::Eprint(_, values) => write!(f, "Eprint {}", Stmt::join(values)),
+            Stmt::Var(_, token, _) => write!(f, "Var {}", token),
+            Stmt::InitialisedVar(_, token, _, expr) => write!(f, "Init Var {} {}", token, expr),
+            Stmt::Block(_, _) => write!(f, "Block Statement"),
+            Stmt::If(_, _, _, _) => write!(f, "If Statement"),
+            Stmt::While(_, _, _) => write!(f, "While Statment"),
+            Stmt::ForIn(_, variable, iterable, _) => {
+                write!(f, "For {} In {}", variable.lexeme, iterable)
+            }
+            Stmt::Function(declaration) => write!(f, "Fun {}", declaration.name.lexeme),
+            Stmt::Class(declaration) => write!(f, "Class {}", declaration.name.lexeme),
+            Stmt::Return(_, Some(expr)) => write!(f, "Return {}", expr),
+            Stmt::Return(_, None) => write!(f, "Return"),
+            Stmt::Enum(declaration) => write!(f, "Enum {}", declaration.name.lexeme),
+            Stmt::Match(_, expr, _) => write!(f, "Match {}", expr),
+            Stmt::Yield(_, Some(expr)) => write!(f, "Yield {}", expr),
+            Stmt::Yield(_, None) => write!(f, "Yield"),
         }
     }
 }
 
-impl<'a> Stmt {
-    pub fn interpret(&self, environment: &mut Box<Environment>, output: &mut String) {
+impl Stmt {
+    /// The source the statement covers, leaving out any closing `;`.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expression(expr) => expr.span(),
+            Stmt::Print(span, _)
+            | Stmt::Eprint(span, _)
+            | Stmt::Var(span, _, _)
+            | Stmt::InitialisedVar(span, _, _, _)
+            | Stmt::Block(span, _)
+            | Stmt::If(span, _, _, _)
+            | Stmt::While(span, _, _)
+            | Stmt::ForIn(span, _, _, _)
+            | Stmt::Match(span, _, _) => *span,
+            Stmt::Function(decl) => decl.span,
+            Stmt::Class(decl) => decl.span,
+            Stmt::Enum(decl) => decl.span,
+            Stmt::Return(keyword, value) | Stmt::Yield(keyword, value) => match value {
+                Some(value) => keyword.span().to(value.span()),
+                None => keyword.span(),
+            },
+        }
+    }
+
+    pub fn interpret(
+        &self,
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Flow, RuntimeError> {
         match self {
             Stmt::Expression(expr) => {
-                expr.interpret(environment);
+                expr.interpret(environment, output)?;
+            }
+            Stmt::Print(_, values) => {
+                let text = Stmt::print_values(values, environment, output)?;
+                writeln!(output.text, "{}", text).unwrap();
             }
-            Stmt::Print(expr) => {
-                writeln!(output, "{}", expr.interpret(environment));
+            Stmt::Eprint(_, values) => {
+                let text = Stmt::print_values(values, environment, output)?;
+                writeln!(output.error_text, "{}", text).unwrap();
             }
-            Stmt::Var(token) => {
-                if let TokenType::Identifier(identifier) = &token.token_type {
-                    environment.set_variable(identifier.to_string(), Value::Nil);
-                } else {
-                    panic!("Expected Identifier");
+            Stmt::Var(_, token, _) => {
+                environment
+                    .borrow_mut()
+                    .set_variable(token.lexeme.clone(), Value::Nil);
+            }
+            Stmt::InitialisedVar(_, token, _, initialiser) => {
+                let value = initialiser.interpret(environment, output)?;
+                environment
+                    .borrow_mut()
+                    .set_variable(token.lexeme.clone(), value);
+            }
+            Stmt::Block(_, stmts) => {
+                let local = Rc::new(RefCell::new(Environment::with_parent(environment.clone())));
+                return Stmt::interpret_block(stmts, &local, output);
+            }
+            Stmt::If(_, condition, consequent, alternate) => {
+                let condition = Expr::is_truthy(condition.interpret(environment, output)?);
+                if condition {
+                    return consequent.interpret(environment, output);
+                } else if let Some(alternate) = alternate {
+                    return alternate.interpret(environment, output);
                 }
             }
-            Stmt::InitialisedVar(token, initialiser) => {
-                if let TokenType::Identifier(identifier) = &token.token_type {
-                    let value = initialiser.interpret(environment);
-                    environment.set_variable(identifier.to_string(), value);
-                    environment.print();
-                } else {
-                    panic!("Expected Identifier");
+            Stmt::While(_, condition, stmt) => {
+                while Expr::is_truthy(condition.interpret(environment, output)?) {
+                    match stmt.interpret(environment, output)? {
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
                 }
             }
-            Stmt::Block(stmts) => {
-                let mut local = Environment::new();
-                local.parent = Some(environment.clone());
-                let mut local = Box::new(local);
-                for stmt in stmts.iter() {
-                    stmt.interpret(&mut local, output);
+            Stmt::ForIn(_, variable, iterable, body) => {
+                let iterable = iterable.interpret(environment, output)?;
+                let mut iter = Iter::new(iterable).map_err(|error| error.or_at(variable))?;
+                while let Some(value) = iter.next(output).map_err(|error| error.or_at(variable))? {
+                    let local =
+                        Rc::new(RefCell::new(Environment::with_parent(environment.clone())));
+                    local
+                        .borrow_mut()
+                        .set_variable(variable.lexeme.clone(), value);
+                    match body.interpret(&local, output)? {
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
                 }
-                std::mem::replace(environment, local);
             }
-            Stmt::If(condition, consequent, alternate) => {
-                let condition = Expr::is_truthy(condition.interpret(environment));
-                if condition {
-                    consequent.interpret(environment, output);
-                } else if let Some(alternate) = alternate {
-                    alternate.interpret(environment, output);
+            Stmt::Function(declaration) => {
+                let function = Function {
+                    declaration: declaration.clone(),
+                    closure: environment.clone(),
+                };
+                environment.borrow_mut().set_variable(
+                    declaration.name.lexeme.clone(),
+                    Value::Function(Rc::new(function)),
+                );
+            }
+            Stmt::Class(declaration) => {
+                let class = Class::new(declaration, environment)?;
+                environment.borrow_mut().set_variable(
+                    declaration.name.lexeme.clone(),
+                    Value::Class(Rc::new(class)),
+                );
+            }
+            Stmt::Return(_, value) => {
+                let value = match value.as_deref() {
+                    Some(Expr::Call {
+                        callee,
+                        arguments,
+                        named,
+                        ..
+                    }) => {
+                        return Ok(Flow::TailCall(Expr::evaluate_call(
+                            callee,
+                            arguments,
+                            named,
+                            environment,
+                            output,
+                        )?));
+                    }
+                    Some(expr) => expr.interpret(environment, output)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Enum(declaration) => {
+                environment.borrow_mut().set_variable(
+                    declaration.name.lexeme.clone(),
+                    Value::Enum(declaration.clone()),
+                );
+            }
+            Stmt::Match(_, scrutinee, arms) => {
+                let value = scrutinee.interpret(environment, output)?;
+                for arm in arms.iter() {
+                    if let Some(local) = arm.pattern.bind(&value, environment)? {
+                        let local = Rc::new(RefCell::new(local));
+                        return arm.body.interpret(&local, output);
+                    }
                 }
             }
-            Stmt::While(condition, stmt) => {
-                while Expr::is_truthy(condition.interpret(environment)) {
-                    stmt.interpret(environment, output);
+            Stmt::Yield(keyword, _) => {
+                let message = String::from("Can only yield from a generator.");
+                return Err(RuntimeError::at(keyword, message));
+            }
+        }
+        return Ok(Flow::Normal);
+    }
+
+    /// The text `print` writes for `values`: each value as it is shown on its
+    /// own, separated by spaces.
+    fn print_values(
+        values: &[Expr],
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<String, RuntimeError> {
+        let mut texts = vec![];
+        for value in values.iter() {
+            texts.push(value.interpret(environment, output)?.stringify(output)?);
+        }
+        return Ok(texts.join(" "));
+    }
+
+    fn join(values: &[Expr]) -> String {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        return values.join(", ");
+    }
+
+    pub fn interpret_block(
+        stmts: &[Stmt],
+        environment: &Rc<RefCell<Environment>>,
+        output: &mut Output,
+    ) -> Result<Flow, RuntimeError> {
+        for stmt in stmts.iter() {
+            match stmt.interpret(environment, output)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        return Ok(Flow::Normal);
+    }
+}
+
+impl Pattern {
+    /// Tests `value` against the pattern, returning a scope holding the
+    /// destructured payload when it matches.
+    pub fn bind(
+        &self,
+        value: &Value,
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<Option<Environment>, RuntimeError> {
+        let mut local = Environment::with_parent(environment.clone());
+        match self {
+            Pattern::Wildcard => {}
+            Pattern::Variant {
+                enum_name,
+                variant,
+                bindings,
+            } => {
+                let declaration = match environment
+                    .borrow()
+                    .get_variable(enum_name.lexeme.clone())
+                    .map_err(|error| error.or_at(enum_name))?
+                {
+                    Value::Enum(declaration) => declaration,
+                    _ => {
+                        let message = format!("'{}' is not an enum.", enum_name.lexeme);
+                        return Err(RuntimeError::at(enum_name, message));
+                    }
+                };
+                let index = match declaration.variant(&variant.lexeme) {
+                    Some(index) => index,
+                    None => {
+                        let message = format!(
+                            "Enum {} has no variant '{}'.",
+                            enum_name.lexeme, variant.lexeme
+                        );
+                        return Err(RuntimeError::at(variant, message));
+                    }
+                };
+                let fields = &declaration.variants[index].fields;
+                if let Some(bindings) = bindings {
+                    if bindings.len() != fields.len() {
+                        let message = format!(
+                            "Variant {}.{} has {} fields but the pattern binds {}.",
+                            enum_name.lexeme,
+                            variant.lexeme,
+                            fields.len(),
+                            bindings.len()
+                        );
+                        return Err(RuntimeError::at(variant, message));
+                    }
+                }
+                match value {
+                    Value::Variant(instance)
+                        if Rc::ptr_eq(&instance.declaration, &declaration)
+                            && instance.index == index =>
+                    {
+                        for (binding, field) in bindings.iter().flatten().zip(&instance.payload) {
+                            local.set_variable(binding.lexeme.clone(), field.clone());
+                        }
+                    }
+                    _ => return Ok(None),
                 }
             }
-            _ => panic!("Unsupported Expression"),
         }
+        return Ok(Some(local));
     }
 }
@@ -7,11 +7,14 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
     Plus,
     Semicolon,
+    Colon,
     Slash,
     Star,
 
@@ -24,6 +27,8 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    FatArrow,
+    Ellipsis,
 
     // Literals.
     Identifier(String),
@@ -34,10 +39,14 @@ pub enum TokenType {
     And,
     Class,
     Else,
+    Enum,
+    Eprint,
     False,
     Fun,
     For,
     If,
+    In,
+    Match,
     Nil,
     Or,
     Print,
@@ -47,7 +56,11 @@ pub enum TokenType {
     True,
     Var,
     While,
+    Yield,
 
+    /// Stands in for source that does not make a token, which the scanner
+    /// has reported.
+    Error,
     Eof,
 }
 
@@ -57,21 +70,64 @@ impl fmt::Display for TokenType {
     }
 }
 
+/// A stretch of a source: the bytes `start..end`, and the line and column
+/// it begins at. Lines and columns count from 1, and columns count
+/// characters rather than bytes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Span {
+    /// Which source the span is in, as numbered by
+    /// `Diagnostics::add_source`.
+    pub source: usize,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`, which comes
+    /// later in the source.
+    pub fn to(self, other: Span) -> Span {
+        return Span {
+            end: self.end.max(other.end),
+            ..self
+        };
+    }
+}
+
 #[derive(Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
+    pub source: usize,
     pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, line: usize) -> Token {
+    pub fn new(token_type: TokenType, lexeme: String, span: Span) -> Token {
         Token {
             token_type,
             lexeme,
-            line,
+            source: span.source,
+            line: span.line,
+            column: span.column,
+            start: span.start,
+            end: span.end,
         }
     }
+
+    pub fn span(&self) -> Span {
+        return Span {
+            source: self.source,
+            start: self.start,
+            end: self.end,
+            line: self.line,
+            column: self.column,
+        };
+    }
 }
 
 impl fmt::Display for Token {
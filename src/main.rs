@@ -1,17 +1,103 @@
-mod lox;
-use lox::expr::Expr;
-use lox::tokens::{Token, TokenType};
+use rlox::lox::{
+    self,
+    diagnostics::{self, Diagnostics},
+};
+
+const USAGE: &str =
+    "Usage: rlox [--allow-read=DIR]... [--allow-write=DIR]... [--seed=N] [--backtrace-limit=N] [--no-prelude] [--error-format=human|json] [check] [script]";
+
+/// Room on the host stack for `lox::MAX_CALL_DEPTH` calls, each of which
+/// takes several interpreter frames, with plenty to spare in debug builds.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
 
 fn main() {
-    let args: Vec<_> = std::env::args().collect();
-    println!("{:?}", args);
-    if args.len() > 2 {
-        println!("Usage: rlox [script]")
-    } else if args.len() == 2 {
-        let mut result = String::new();
-        lox::run_file(&args[1], &mut result);
-        println!("{}", result);
+    let cli = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(cli)
+        .unwrap();
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn cli() {
+    let mut options = lox::Options::default();
+    let mut json = false;
+    let mut args: Vec<String> = vec![];
+    for arg in std::env::args().skip(1) {
+        let granted = if let Some(directory) = arg.strip_prefix("--allow-read=") {
+            options.sandbox.allow_read(directory)
+        } else if let Some(directory) = arg.strip_prefix("--allow-write=") {
+            options.sandbox.allow_write(directory)
+        } else if let Some(seed) = arg.strip_prefix("--seed=") {
+            match seed.parse() {
+                Ok(seed) => options.seed = Some(seed),
+                Err(_) => {
+                    println!("Invalid seed '{}': expected a whole number.", seed);
+                    std::process::exit(64);
+                }
+            }
+            continue;
+        } else if let Some(limit) = arg.strip_prefix("--backtrace-limit=") {
+            match limit.parse() {
+                Ok(limit) => options.backtrace_limit = limit,
+                Err(_) => {
+                    println!(
+                        "Invalid backtrace limit '{}': expected a whole number.",
+                        limit
+                    );
+                    std::process::exit(64);
+                }
+            }
+            continue;
+        } else if let Some(format) = arg.strip_prefix("--error-format=") {
+            match format {
+                "human" => json = false,
+                "json" => json = true,
+                _ => {
+                    println!("Invalid error format '{}': expected human or json.", format);
+                    std::process::exit(64);
+                }
+            }
+            continue;
+        } else if arg == "--no-prelude" {
+            options.no_prelude = true;
+            continue;
+        } else if arg.starts_with("--") {
+            println!("{}", USAGE);
+            std::process::exit(64);
+        } else {
+            args.push(arg);
+            continue;
+        };
+        if let Err(error) = granted {
+            println!("Cannot grant access to {}: {}", arg, error);
+            std::process::exit(64);
+        }
+    }
+
+    let mut diagnostics = Diagnostics::new();
+    if args.len() == 2 && args[0] == "check" {
+        lox::check_file(&args[1], &mut diagnostics);
+    } else if args.len() > 1 {
+        println!("{}", USAGE)
+    } else if args.len() == 1 {
+        let mut output = lox::Output::default();
+        lox::run_file(&args[0], &options, &mut output, &mut diagnostics);
+        println!("{}", output.text);
+        eprint!("{}", output.error_text);
     } else {
-        lox::run_prompt();
+        lox::run_prompt(&options, json);
+    }
+    let colour = diagnostics::colour_stderr();
+    for diagnostic in diagnostics.iter() {
+        if json {
+            eprintln!("{}", diagnostics.to_json(diagnostic));
+        } else {
+            eprintln!("{}", diagnostics.render(diagnostic, colour));
+        }
+    }
+    if diagnostics.has_errors() {
+        std::process::exit(65);
     }
 }